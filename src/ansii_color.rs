@@ -0,0 +1,103 @@
+//! The xterm 256-color palette, and mapping 24-bit colors onto it.
+
+use std::ops::RangeInclusive;
+use std::sync::OnceLock;
+
+use crate::twenty_four_bit_color::RGBColor;
+
+/// Channel levels of the 6x6x6 color cube at codes 16-231.
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// xterm's default colors for the 16 "system" codes.
+const SYSTEM_COLORS: [u32; 16] = [
+    0x000000, 0xcd0000, 0x00cd00, 0xcdcd00, 0x0000ee, 0xcd00cd, 0x00cdcd, 0xe5e5e5,
+    0x7f7f7f, 0xff0000, 0x00ff00, 0xffff00, 0x5c5cff, 0xff00ff, 0x00ffff, 0xffffff,
+];
+
+/// The color xterm displays for 256-color `code`.
+pub fn palette_color(code: u8) -> RGBColor {
+    match code {
+        0..=15 => RGBColor::from(SYSTEM_COLORS[code as usize]),
+        16..=231 => {
+            let n = code - 16;
+            RGBColor {
+                red: CUBE_LEVELS[(n / 36) as usize],
+                green: CUBE_LEVELS[(n / 6 % 6) as usize],
+                blue: CUBE_LEVELS[(n % 6) as usize],
+            }
+        }
+        232..=255 => {
+            let level = 8 + 10 * (code - 232);
+            RGBColor { red: level, green: level, blue: level }
+        }
+    }
+}
+
+/// The 256-color code that looks closest to `color`.
+///
+/// Only codes 16-255 are considered: the system colors are commonly
+/// redefined by terminal themes, so they can't be relied on to look like
+/// anything in particular.
+///
+/// Closeness is measured in the Oklab color space, where distance tracks
+/// perceived difference much better than it does in plain RGB.
+pub fn nearest(color: RGBColor) -> u8 {
+    nearest_in(color, 16..=255)
+}
+
+/// The code out of `codes` that looks closest to `color`, as with [`nearest`].
+///
+/// Use `0..=15` or `0..=7` to pick from the basic colors that even limited
+/// terminals understand, at the mercy of whatever theme they're using.
+pub fn nearest_in(color: RGBColor, codes: RangeInclusive<u8>) -> u8 {
+    static PALETTE_LAB: OnceLock<[Lab; 256]> = OnceLock::new();
+    let palette = PALETTE_LAB.get_or_init(|| std::array::from_fn(|code| Lab::from(palette_color(code as u8))));
+
+    let target = Lab::from(color);
+    codes
+        .min_by(|a: &u8, b: &u8| {
+            let da = target.distance_squared(&palette[*a as usize]);
+            let db = target.distance_squared(&palette[*b as usize]);
+            da.total_cmp(&db)
+        })
+        .expect("codes shouldn't be empty")
+}
+
+/// A color in the Oklab space: <https://bottosson.github.io/posts/oklab/>
+struct Lab {
+    l: f32,
+    a: f32,
+    b: f32,
+}
+
+impl Lab {
+    fn distance_squared(&self, other: &Lab) -> f32 {
+        (self.l - other.l).powi(2) + (self.a - other.a).powi(2) + (self.b - other.b).powi(2)
+    }
+}
+
+impl From<RGBColor> for Lab {
+    #[allow(clippy::excessive_precision)] // coefficients as published
+    fn from(color: RGBColor) -> Self {
+        fn linear(c: u8) -> f32 {
+            let c = c as f32 / 255.0;
+            if c <= 0.04045 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            }
+        }
+
+        let (r, g, b) = (linear(color.red), linear(color.green), linear(color.blue));
+
+        let l = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
+        let m = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+        let s = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+
+        Lab {
+            l: 0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+            a: 1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+            b: 0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s,
+        }
+    }
+}
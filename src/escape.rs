@@ -0,0 +1,384 @@
+//! Telling printable text apart from escape sequences and control characters.
+//!
+//! This is a state machine along the lines of ECMA-48 (and the DEC parser
+//! described at <https://vt100.net/emu/dec_ansi_parser>), simplified down to
+//! what's needed to find where sequences start and end. It understands:
+//!  - two-character escapes and charset selections like `ESC 7` and `ESC ( B`,
+//!  - control sequences (CSI) like `ESC [ 1 ; 31 m` and `ESC [ 1 5 ~`,
+//!  - control strings (OSC, DCS, SOS, PM and APC), like the hyperlink
+//!    `ESC ] 8 ; ; url ESC \`, ended by ST or BEL,
+//!  - the 8-bit C1 forms of all of the above.
+
+use crate::ESCAPE_CHAR as ESC;
+use crate::StripMode;
+
+const BEL: char = '\x07';
+const CAN: char = '\x18';
+const SUB: char = '\x1a';
+
+// 8-bit C1 controls
+const C1_DCS: char = '\u{90}';
+const C1_SOS: char = '\u{98}';
+const C1_CSI: char = '\u{9b}';
+const C1_ST: char = '\u{9c}';
+const C1_OSC: char = '\u{9d}';
+const C1_PM: char = '\u{9e}';
+const C1_APC: char = '\u{9f}';
+
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub(crate) enum EscapeState {
+    #[default]
+    Ground,
+    /// Just after ESC.
+    Escape,
+    /// After ESC and one or more intermediate bytes, e.g. `ESC (`.
+    EscapeIntermediate,
+    /// In a control sequence's parameters or intermediates.
+    Csi,
+    /// In the body of a control string (OSC, DCS, SOS, PM or APC).
+    String,
+    /// ESC inside a control string; either the start of ST, or an abort.
+    StringEscape,
+}
+
+/// What a character turned out to be, given the characters before it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum CharKind {
+    /// Text that will show up on screen.
+    Printable,
+    /// A control character on its own, like a newline or tab.
+    Control,
+    /// Part of an escape sequence, but not its last character.
+    InSequence,
+    /// The last character of an escape sequence.
+    SequenceEnd,
+}
+
+impl EscapeState {
+    pub(crate) fn advance(&mut self, c: char) -> CharKind {
+        use CharKind::*;
+        use EscapeState::*;
+
+        // these work the same from (almost) anywhere
+        let in_string = matches!(*self, String | StringEscape);
+        match c {
+            CAN | SUB if *self != Ground => {
+                // cancels the sequence
+                *self = Ground;
+                return SequenceEnd;
+            }
+            ESC if !in_string => {
+                *self = Escape;
+                return InSequence;
+            }
+            C1_CSI if !in_string => {
+                *self = Csi;
+                return InSequence;
+            }
+            C1_OSC | C1_DCS | C1_SOS | C1_PM | C1_APC if !in_string => {
+                *self = String;
+                return InSequence;
+            }
+            _ => {}
+        }
+
+        match *self {
+            Ground => {
+                if c.is_control() {
+                    Control
+                } else {
+                    Printable
+                }
+            }
+
+            Escape => match c {
+                '[' => {
+                    *self = Csi;
+                    InSequence
+                }
+                ']' | 'P' | 'X' | '^' | '_' => {
+                    *self = String;
+                    InSequence
+                }
+                '\x20'..='\x2f' => {
+                    *self = EscapeIntermediate;
+                    InSequence
+                }
+                '\x30'..='\x7e' => {
+                    *self = Ground;
+                    SequenceEnd
+                }
+                // controls are still carried out in the middle of a sequence
+                _ if c.is_ascii_control() => InSequence,
+                _ => {
+                    // not a valid sequence; give up on it
+                    *self = Ground;
+                    Printable
+                }
+            },
+
+            EscapeIntermediate => match c {
+                '\x20'..='\x2f' => InSequence,
+                '\x30'..='\x7e' => {
+                    *self = Ground;
+                    SequenceEnd
+                }
+                _ if c.is_ascii_control() => InSequence,
+                _ => {
+                    *self = Ground;
+                    Printable
+                }
+            },
+
+            Csi => match c {
+                '\x20'..='\x3f' => InSequence,
+                '\x40'..='\x7e' => {
+                    *self = Ground;
+                    SequenceEnd
+                }
+                _ if c.is_ascii_control() => InSequence,
+                _ => {
+                    *self = Ground;
+                    Printable
+                }
+            },
+
+            String => match c {
+                BEL | C1_ST => {
+                    *self = Ground;
+                    SequenceEnd
+                }
+                ESC => {
+                    *self = StringEscape;
+                    InSequence
+                }
+                _ => InSequence,
+            },
+
+            StringEscape => match c {
+                '\\' => {
+                    *self = Ground;
+                    SequenceEnd
+                }
+                _ => {
+                    // anything else cancels the string, and starts a new escape
+                    *self = Escape;
+                    self.advance(c)
+                }
+            },
+        }
+    }
+}
+
+/// Whether the foreground color is set to something other than the default
+/// after an SGR sequence (`CSI ... m`) with these parameters.
+///
+/// `set` is whether it was before the sequence.
+pub(crate) fn sgr_sets_foreground(params: &str, mut set: bool) -> bool {
+    if !params.bytes().all(|b| b.is_ascii_digit() || b == b';' || b == b':') {
+        // private or intermediate bytes; not a plain SGR
+        return set;
+    }
+
+    let code = |param: &str| param.split(':').next().and_then(|n| n.parse::<u16>().ok()).unwrap_or(0);
+
+    let mut params = params.split(';');
+    while let Some(param) = params.next() {
+        match code(param) {
+            0 | 39 => set = false,
+            30..=37 | 90..=97 => set = true,
+            extended @ (38 | 48 | 58) => {
+                if extended == 38 {
+                    set = true;
+                }
+                // with ';' instead of ':', the color's parts are separate
+                // parameters that need skipping
+                if !param.contains(':') {
+                    let skip = match params.next().map(code) {
+                        Some(5) => 1,
+                        Some(2) => 3,
+                        _ => 0,
+                    };
+                    params.by_ref().take(skip).for_each(drop);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    set
+}
+
+/// Removes escape sequences from text as it streams past.
+///
+/// Telling whether a sequence is SGR means waiting for its final character,
+/// so the start of a possible SGR sequence is held back until then.
+#[derive(Clone, Default)]
+pub(crate) struct Stripper {
+    state: EscapeState,
+    held: String,
+}
+
+impl Stripper {
+    pub(crate) fn feed(&mut self, mode: StripMode, c: char, out: &mut String) {
+        use EscapeState::*;
+
+        let prev = self.state;
+        let kind = self.state.advance(c);
+
+        match (mode, kind) {
+            (_, CharKind::Printable | CharKind::Control) => {
+                self.flush(out);
+                out.push(c);
+            }
+
+            (StripMode::All, CharKind::InSequence | CharKind::SequenceEnd) => {}
+
+            (_, CharKind::InSequence) => {
+                let could_be_sgr = matches!(self.state, Escape | Csi);
+                let starts_sequence = c == ESC || c == C1_CSI;
+                if !(could_be_sgr && (starts_sequence || matches!(prev, Escape | Csi))) {
+                    self.flush(out);
+                    out.push(c);
+                } else {
+                    if starts_sequence {
+                        // the sequence being held (if any) was abandoned
+                        self.flush(out);
+                    }
+                    self.held.push(c);
+                }
+            }
+
+            (_, CharKind::SequenceEnd) => {
+                let params = self.held.strip_prefix(ESC).and_then(|h| h.strip_prefix('['))
+                    .or_else(|| self.held.strip_prefix(C1_CSI));
+                let is_sgr = prev == Csi && c == 'm'
+                    && params.is_some_and(|p| p.bytes().all(|b| b.is_ascii_digit() || b == b';' || b == b':'));
+                if is_sgr || mode == StripMode::All {
+                    self.held.clear();
+                } else {
+                    self.flush(out);
+                    out.push(c);
+                }
+            }
+        }
+    }
+
+    /// Whether it's between sequences, with nothing held back.
+    pub(crate) fn is_idle(&self) -> bool {
+        self.state == EscapeState::Ground && self.held.is_empty()
+    }
+
+    pub(crate) fn held_len(&self) -> usize {
+        self.held.chars().count()
+    }
+
+    /// Give back anything held, e.g. at the end of the input.
+    pub(crate) fn flush(&mut self, out: &mut String) {
+        out.push_str(&self.held);
+        self.held.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use CharKind::*;
+
+    fn kinds(input: &str) -> Vec<CharKind> {
+        let mut state = EscapeState::Ground;
+        input.chars().map(|c| state.advance(c)).collect()
+    }
+
+    /// Just the characters that show up on screen.
+    fn printable(input: &str) -> String {
+        let mut state = EscapeState::Ground;
+        input.chars().filter(|c| state.advance(*c) == Printable).collect()
+    }
+
+    fn strip(mode: StripMode, input: &str) -> String {
+        let mut stripper = Stripper::default();
+        let mut out = String::new();
+        for c in input.chars() {
+            stripper.feed(mode, c, &mut out);
+        }
+        stripper.flush(&mut out);
+        out
+    }
+
+    #[test]
+    fn hyperlink_ended_by_st() {
+        let link = "\x1b]8;;https://example.com\x1b\\";
+        assert_eq!(printable(&format!("{link}link\x1b]8;;\x1b\\!")), "link!");
+        assert_eq!(kinds(link).last(), Some(&SequenceEnd));
+    }
+
+    #[test]
+    fn hyperlink_ended_by_bel() {
+        let link = "\x1b]8;;https://example.com\x07";
+        assert_eq!(printable(&format!("{link}link\x1b]8;;\x07!")), "link!");
+        assert_eq!(kinds(link).last(), Some(&SequenceEnd));
+    }
+
+    #[test]
+    fn charset_selection() {
+        assert_eq!(kinds("\x1b(Bx"), [InSequence, InSequence, SequenceEnd, Printable]);
+    }
+
+    #[test]
+    fn csi_with_tilde() {
+        assert_eq!(kinds("\x1b[15~x"), [InSequence, InSequence, InSequence, InSequence, SequenceEnd, Printable]);
+    }
+
+    #[test]
+    fn can_aborts_sequences() {
+        assert_eq!(printable("\x1b[12\x18ab"), "ab");
+        assert_eq!(printable("\x1b]8;;url\x18ab"), "ab");
+        // outside a sequence it's just a control character
+        assert_eq!(kinds("\x18"), [Control]);
+    }
+
+    #[test]
+    fn c1_controls() {
+        assert_eq!(printable("\u{9b}31mred\u{9d}8;;url\u{9c}x"), "redx");
+    }
+
+    #[test]
+    fn foreground_tracking() {
+        assert!(sgr_sets_foreground("31", false));
+        assert!(!sgr_sets_foreground("1;39", true));
+        assert!(!sgr_sets_foreground("", true));
+        // the 31 here is part of a background color
+        assert!(!sgr_sets_foreground("48;2;31;31;31", false));
+        assert!(sgr_sets_foreground("38:5:200", false));
+        assert!(sgr_sets_foreground("?25", true));
+    }
+
+    #[test]
+    fn strip_sgr_keeps_other_sequences() {
+        assert_eq!(strip(StripMode::Sgr, "\x1b[1;31mred\x1b[0m \x1b[2K\x1b]8;;url\x07link"), "red \x1b[2K\x1b]8;;url\x07link");
+        // private parameters mean it isn't plain SGR
+        assert_eq!(strip(StripMode::Sgr, "\x1b[?25m"), "\x1b[?25m");
+    }
+
+    #[test]
+    fn strip_all() {
+        assert_eq!(strip(StripMode::All, "\x1b[1;31mred\x1b[0m \x1b[2K\x1b]8;;url\x1b\\link\x1b(B"), "red link");
+    }
+
+    #[test]
+    fn stripper_holds_possible_sgr() {
+        let mut stripper = Stripper::default();
+        let mut out = String::new();
+        for c in "a\x1b[3".chars() {
+            stripper.feed(StripMode::Sgr, c, &mut out);
+        }
+        assert_eq!(out, "a");
+        assert!(!stripper.is_idle());
+        assert_eq!(stripper.held_len(), 3);
+
+        // an unfinished sequence is given back at the end
+        stripper.flush(&mut out);
+        assert_eq!(out, "a\x1b[3");
+    }
+}
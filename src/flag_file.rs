@@ -0,0 +1,675 @@
+//! Loading extra flag definitions from files.
+//!
+//! Two formats are understood. The first is a small subset of TOML, with one
+//! `[[flag]]` table per flag (or just bare keys, for a file holding a single
+//! flag):
+//!
+//! ```toml
+//! [[flag]]
+//! name = "demisexual"
+//! stripes = ["#000000", "#a3a3a3", "#ffffff", "#800080"]
+//! ansii_pattern = [233, 233, 247, 247, 255, 255, 5, 5]
+//! ```
+//!
+//! The second is JSON, holding either one such object or an array of them.
+//!
+//! Stripes may be given as `"#rrggbb"` (or `"#rgb"`) strings or as integers (`0xrrggbb` in
+//! TOML). The 256-color pattern is a list of xterm color indices; if it's
+//! left out, one is derived from the stripes. `factor`, the sharpness of
+//! the blend between stripes, defaults to [`ColorStripes::DEFAULT_FACTOR`].
+
+use std::borrow::Cow;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::twenty_four_bit_color::{parse_hex_color, ColorPattern, ColorStripes};
+use crate::{ColorPattern_Ansii, FlagDefinition};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FlagFileFormat {
+    Toml,
+    Json,
+}
+
+impl FlagFileFormat {
+    /// Guess the format from a file's extension, defaulting to TOML.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension() {
+            Some(ext) if ext.eq_ignore_ascii_case("json") => FlagFileFormat::Json,
+            _ => FlagFileFormat::Toml,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum FlagFileError {
+    Io(std::io::Error),
+    Invalid { line: usize, message: String },
+}
+
+impl fmt::Display for FlagFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FlagFileError::Io(e) => e.fmt(f),
+            FlagFileError::Invalid { line, message } => write!(f, "line {line}: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for FlagFileError {}
+
+impl From<std::io::Error> for FlagFileError {
+    fn from(value: std::io::Error) -> Self {
+        FlagFileError::Io(value)
+    }
+}
+
+fn invalid<T>(line: usize, message: impl Into<String>) -> Result<T, FlagFileError> {
+    Err(FlagFileError::Invalid { line, message: message.into() })
+}
+
+/// Read and parse the flag definitions in the file at `path`.
+pub fn load_flag_file(path: impl AsRef<Path>) -> Result<Vec<FlagDefinition>, FlagFileError> {
+    let path = path.as_ref();
+    let text = std::fs::read_to_string(path)?;
+    parse_flag_file(&text, FlagFileFormat::from_path(path))
+}
+
+/// Parse the flag definitions in `text`.
+pub fn parse_flag_file(text: &str, format: FlagFileFormat) -> Result<Vec<FlagDefinition>, FlagFileError> {
+    let mut cursor = Cursor::new(text);
+    let tables = match format {
+        FlagFileFormat::Toml => parse_toml(&mut cursor)?,
+        FlagFileFormat::Json => parse_json(&mut cursor)?,
+    };
+    tables.into_iter().map(build_flag).collect()
+}
+
+enum Value {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Array(Vec<(usize, Value)>),
+}
+
+impl Value {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "a string",
+            Value::Integer(_) => "an integer",
+            Value::Float(_) => "a number",
+            Value::Array(_) => "a list",
+        }
+    }
+}
+
+/// The keys of one flag, each with the line it was found on.
+struct Table {
+    line: usize,
+    entries: Vec<(usize, String, Value)>,
+}
+
+impl Table {
+    fn new(line: usize) -> Self {
+        Table { line, entries: Vec::new() }
+    }
+
+    fn insert(&mut self, line: usize, key: String, value: Value) -> Result<(), FlagFileError> {
+        if self.entries.iter().any(|(_, k, _)| *k == key) {
+            return invalid(line, format!["duplicate key '{key}'"]);
+        }
+        self.entries.push((line, key, value));
+        Ok(())
+    }
+}
+
+struct Cursor<'t> {
+    chars: std::iter::Peekable<std::str::Chars<'t>>,
+    line: usize,
+}
+
+impl<'t> Cursor<'t> {
+    fn new(text: &'t str) -> Self {
+        Cursor { chars: text.chars().peekable(), line: 1 }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), FlagFileError> {
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.bump();
+                Ok(())
+            }
+            Some(c) => invalid(self.line, format!["expected '{expected}', found '{c}'"]),
+            None => invalid(self.line, format!["expected '{expected}', found end of file"]),
+        }
+    }
+
+    /// Skip spaces and tabs, and a `#` comment, but not the end of the line.
+    fn skip_inline(&mut self) {
+        while let Some(c) = self.peek() {
+            match c {
+                ' ' | '\t' | '\r' => {
+                    self.bump();
+                }
+                '#' => {
+                    while self.peek().is_some_and(|c| c != '\n') {
+                        self.bump();
+                    }
+                }
+                _ => break,
+            }
+        }
+    }
+
+    /// Skip whitespace, newlines and comments.
+    fn skip_blank(&mut self) {
+        loop {
+            self.skip_inline();
+            if self.peek() == Some('\n') {
+                self.bump();
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn parse_string(&mut self) -> Result<String, FlagFileError> {
+        let start_line = self.line;
+        self.expect('"')?;
+        let mut s = String::new();
+        loop {
+            match self.bump() {
+                None | Some('\n') => return invalid(start_line, "unterminated string"),
+                Some('"') => return Ok(s),
+                Some('\\') => match self.bump() {
+                    Some('"') => s.push('"'),
+                    Some('\\') => s.push('\\'),
+                    Some('/') => s.push('/'),
+                    Some('n') => s.push('\n'),
+                    Some('t') => s.push('\t'),
+                    Some('r') => s.push('\r'),
+                    Some('b') => s.push('\x08'),
+                    Some('f') => s.push('\x0c'),
+                    Some('u') => s.push(self.parse_unicode_escape()?),
+                    Some(c) => return invalid(self.line, format!["invalid escape '\\{c}'"]),
+                    None => return invalid(start_line, "unterminated string"),
+                },
+                Some(c) => s.push(c),
+            }
+        }
+    }
+
+    /// The rest of a `\uXXXX` escape, including the second half of a
+    /// surrogate pair, as JSON writes characters outside the BMP.
+    fn parse_unicode_escape(&mut self) -> Result<char, FlagFileError> {
+        let line = self.line;
+        let hex4 = |cursor: &mut Self| {
+            let hex: String = (0..4).filter_map(|_| cursor.bump()).collect();
+            u32::from_str_radix(&hex, 16).ok().filter(|_| hex.len() == 4).ok_or(hex)
+        };
+        let bad = |hex: String| invalid(line, format!["invalid escape '\\u{hex}'"]);
+
+        let first = match hex4(self) {
+            Ok(n) => n,
+            Err(hex) => return bad(hex),
+        };
+        let code = match first {
+            0xd800..=0xdbff => {
+                if self.bump() != Some('\\') || self.bump() != Some('u') {
+                    return invalid(line, format!["unpaired surrogate '\\u{first:04x}'"]);
+                }
+                match hex4(self) {
+                    Ok(second @ 0xdc00..=0xdfff) => 0x10000 + ((first - 0xd800) << 10) + (second - 0xdc00),
+                    Ok(_) => return invalid(line, format!["unpaired surrogate '\\u{first:04x}'"]),
+                    Err(hex) => return bad(hex),
+                }
+            }
+            n => n,
+        };
+        match char::from_u32(code) {
+            Some(c) => Ok(c),
+            None => invalid(line, format!["unpaired surrogate '\\u{first:04x}'"]),
+        }
+    }
+
+    fn parse_number(&mut self) -> Result<Value, FlagFileError> {
+        let line = self.line;
+        let mut text = String::new();
+        while let Some(c) = self.peek().filter(|c| c.is_ascii_alphanumeric() || "+-._".contains(*c)) {
+            text.push(c);
+            self.bump();
+        }
+        let digits = text.replace('_', "");
+
+        let value = if let Some(hex) = digits.strip_prefix("0x") {
+            i64::from_str_radix(hex, 16).ok().map(Value::Integer)
+        } else if digits.contains(['.', 'e', 'E']) {
+            digits.parse().ok().map(Value::Float)
+        } else {
+            digits.parse().ok().map(Value::Integer)
+        };
+
+        match value {
+            Some(v) => Ok(v),
+            None => invalid(line, format!["invalid number '{text}'"]),
+        }
+    }
+
+    fn parse_array(&mut self) -> Result<Value, FlagFileError> {
+        self.expect('[')?;
+        let mut items = Vec::new();
+        loop {
+            self.skip_blank();
+            if self.peek() == Some(']') {
+                self.bump();
+                return Ok(Value::Array(items));
+            }
+            let line = self.line;
+            items.push((line, self.parse_value()?));
+            self.skip_blank();
+            match self.peek() {
+                Some(',') => {
+                    self.bump();
+                }
+                Some(']') => {}
+                _ => self.expect(']')?,
+            }
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<Value, FlagFileError> {
+        match self.peek() {
+            Some('"') => self.parse_string().map(Value::String),
+            Some('[') => self.parse_array(),
+            Some(c) if c.is_ascii_digit() || c == '-' || c == '+' => self.parse_number(),
+            Some(c) => invalid(self.line, format!["expected a value, found '{c}'"]),
+            None => invalid(self.line, "expected a value, found end of file"),
+        }
+    }
+}
+
+fn parse_toml(cursor: &mut Cursor) -> Result<Vec<Table>, FlagFileError> {
+    let mut tables = Vec::new();
+    let mut bare_keys = false;
+
+    loop {
+        cursor.skip_blank();
+        let line = cursor.line;
+        match cursor.peek() {
+            None => break,
+            Some('[') => {
+                if bare_keys {
+                    return invalid(line, "'[[flag]]' can't follow keys outside of a table");
+                }
+                let mut header = String::new();
+                while let Some(c) = cursor.peek().filter(|c| *c != '\n' && *c != '#') {
+                    header.push(c);
+                    cursor.bump();
+                }
+                if header.trim_end() != "[[flag]]" {
+                    return invalid(line, format!["unknown table '{}', expected '[[flag]]'", header.trim_end()]);
+                }
+                tables.push(Table::new(line));
+            }
+            Some(_) => {
+                let key = if cursor.peek() == Some('"') {
+                    cursor.parse_string()?
+                } else {
+                    let mut key = String::new();
+                    while let Some(c) = cursor.peek().filter(|c| c.is_ascii_alphanumeric() || *c == '_' || *c == '-') {
+                        key.push(c);
+                        cursor.bump();
+                    }
+                    key
+                };
+                if key.is_empty() {
+                    return invalid(line, "expected a key or '[[flag]]'");
+                }
+
+                cursor.skip_inline();
+                cursor.expect('=')?;
+                cursor.skip_inline();
+                let value = cursor.parse_value()?;
+                cursor.skip_inline();
+                if cursor.peek().is_some_and(|c| c != '\n') {
+                    return invalid(cursor.line, "expected end of line after value");
+                }
+
+                if tables.is_empty() {
+                    bare_keys = true;
+                    tables.push(Table::new(line));
+                }
+                let table = tables.last_mut().expect("just checked");
+                table.insert(line, key, value)?;
+            }
+        }
+    }
+
+    if tables.is_empty() {
+        return invalid(cursor.line, "no flags defined");
+    }
+    Ok(tables)
+}
+
+fn parse_json(cursor: &mut Cursor) -> Result<Vec<Table>, FlagFileError> {
+    fn parse_object(cursor: &mut Cursor) -> Result<Table, FlagFileError> {
+        let mut table = Table::new(cursor.line);
+        cursor.expect('{')?;
+        loop {
+            cursor.skip_blank();
+            if cursor.peek() == Some('}') {
+                cursor.bump();
+                return Ok(table);
+            }
+            let line = cursor.line;
+            let key = cursor.parse_string()?;
+            cursor.skip_blank();
+            cursor.expect(':')?;
+            cursor.skip_blank();
+            let value = cursor.parse_value()?;
+            table.insert(line, key, value)?;
+            cursor.skip_blank();
+            match cursor.peek() {
+                Some(',') => {
+                    cursor.bump();
+                }
+                Some('}') => {}
+                _ => cursor.expect('}')?,
+            }
+        }
+    }
+
+    cursor.skip_blank();
+    let tables = match cursor.peek() {
+        Some('{') => vec![parse_object(cursor)?],
+        Some('[') => {
+            cursor.bump();
+            let mut tables = Vec::new();
+            loop {
+                cursor.skip_blank();
+                match cursor.peek() {
+                    Some(']') => {
+                        cursor.bump();
+                        break;
+                    }
+                    Some('{') => tables.push(parse_object(cursor)?),
+                    _ => cursor.expect('{')?,
+                }
+                cursor.skip_blank();
+                match cursor.peek() {
+                    Some(',') => {
+                        cursor.bump();
+                    }
+                    Some(']') => {}
+                    _ => cursor.expect(']')?,
+                }
+            }
+            tables
+        }
+        _ => return invalid(cursor.line, "expected a flag object or a list of them"),
+    };
+
+    cursor.skip_blank();
+    if let Some(c) = cursor.peek() {
+        return invalid(cursor.line, format!["unexpected '{c}' after end of flags"]);
+    }
+    if tables.is_empty() {
+        return invalid(cursor.line, "no flags defined");
+    }
+    Ok(tables)
+}
+
+fn build_flag(table: Table) -> Result<FlagDefinition, FlagFileError> {
+    let mut name = None;
+    let mut stripes = None;
+    let mut factor = None;
+    let mut ansii_pattern = None;
+
+    for (line, key, value) in table.entries {
+        let mistyped = |expected: &str| invalid(line, format!["'{key}' should be {expected}, not {}", value.type_name()]);
+
+        match key.as_str() {
+            "name" => {
+                let Value::String(s) = value else { return mistyped("a string") };
+                if s.is_empty() {
+                    return invalid(line, "flag name can't be empty");
+                }
+                if s.parse::<usize>().is_ok() {
+                    return invalid(line, format!["flag name '{s}' can't be a number"]);
+                }
+                name = Some(s);
+            }
+            "stripes" => {
+                let Value::Array(ref items) = value else { return mistyped("a list of colors") };
+                if items.is_empty() {
+                    return invalid(line, "'stripes' can't be empty");
+                }
+                stripes = Some(items.iter().map(|(line, item)| parse_stripe(*line, item)).collect::<Result<Vec<_>, _>>()?);
+            }
+            "factor" => {
+                let f = match value {
+                    Value::Float(f) => f as f32,
+                    Value::Integer(i) => i as f32,
+                    _ => return mistyped("a number"),
+                };
+                if !(f.is_finite() && f > 0.0) {
+                    return invalid(line, "'factor' must be a positive number");
+                }
+                factor = Some(f);
+            }
+            "ansii_pattern" => {
+                let Value::Array(ref items) = value else { return mistyped("a list of color codes") };
+                if items.is_empty() {
+                    return invalid(line, "'ansii_pattern' can't be empty");
+                }
+                ansii_pattern = Some(items.iter().map(|(line, item)| match item {
+                    Value::Integer(i) => u8::try_from(*i)
+                        .or_else(|_| invalid(*line, format!["color code {i} is out of range 0-255"])),
+                    other => invalid(*line, format!["color codes should be integers, not {}", other.type_name()]),
+                }).collect::<Result<Vec<_>, _>>()?);
+            }
+            _ => return invalid(line, format!["unknown key '{key}'"]),
+        }
+    }
+
+    let missing = |key: &str| invalid(table.line, format!["flag is missing '{key}'"]);
+    let Some(name) = name else { return missing("name") };
+    let Some(stripes) = stripes else { return missing("stripes") };
+    let factor = factor.unwrap_or(ColorStripes::DEFAULT_FACTOR);
+
+    Ok(match ansii_pattern {
+        Some(ansii_pattern) => FlagDefinition {
+            name: Cow::Owned(name),
+            ansii_pattern: ColorPattern_Ansii(Cow::Owned(ansii_pattern)),
+            color_pattern: ColorPattern::Stripes(ColorStripes {
+                stripes: Cow::Owned(stripes),
+                factor,
+            }),
+        },
+        None => match FlagDefinition::from_stripes(name, stripes, factor) {
+            Some(flag) => flag,
+            None => return invalid(table.line, "'stripes' can't be empty"),
+        },
+    })
+}
+
+fn parse_stripe(line: usize, value: &Value) -> Result<u32, FlagFileError> {
+    match value {
+        Value::String(s) => parse_hex_color(s)
+            .map_or_else(|| invalid(line, format!["invalid color '{s}', expected '#rrggbb'"]), Ok),
+        Value::Integer(i) => u32::try_from(*i).ok()
+            .filter(|c| *c <= 0xffffff)
+            .map_or_else(|| invalid(line, format!["color {i:#x} is out of range"]), Ok),
+        other => invalid(line, format!["colors should be strings or integers, not {}", other.type_name()]),
+    }
+}
+
+/// Directories searched for a shared library of flag files, in priority order.
+///
+/// That's `$XDG_CONFIG_HOME/queercat/flags` (or `~/.config/queercat/flags`),
+/// then `queercat/flags` under each of `$XDG_DATA_DIRS` (or `/usr/local/share`
+/// and `/usr/share`).
+pub fn library_dirs() -> Vec<PathBuf> {
+    use std::env::{split_paths, var_os};
+
+    let absolute = |p: PathBuf| Some(p).filter(|p| p.is_absolute());
+
+    let config_home = var_os("XDG_CONFIG_HOME").map(PathBuf::from).and_then(absolute)
+        .or_else(|| var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+
+    let data_dirs: Vec<PathBuf> = var_os("XDG_DATA_DIRS")
+        .map(|dirs| split_paths(&dirs).filter_map(absolute).collect())
+        .filter(|dirs: &Vec<PathBuf>| !dirs.is_empty())
+        .unwrap_or_else(|| vec!["/usr/local/share".into(), "/usr/share".into()]);
+
+    config_home.into_iter()
+        .chain(data_dirs)
+        .map(|dir| dir.join("queercat").join("flags"))
+        .collect()
+}
+
+/// The flags loaded from one file in a library directory, or why they couldn't be.
+pub type LibraryFile = (PathBuf, Result<Vec<FlagDefinition>, FlagFileError>);
+
+/// Load every `.toml` and `.json` file in `dir`, in file name order.
+///
+/// A missing directory just has no flags in it. Each file is loaded
+/// independently, so one bad file doesn't hide the rest.
+pub fn load_flag_dir(dir: impl AsRef<Path>) -> std::io::Result<Vec<LibraryFile>> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut paths = entries
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    paths.retain(|path| {
+        path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("toml") || ext.eq_ignore_ascii_case("json"))
+    });
+    paths.sort();
+
+    Ok(paths.into_iter()
+        .map(|path| {
+            let flags = load_flag_file(&path);
+            (path, flags)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str, format: FlagFileFormat) -> Vec<FlagDefinition> {
+        parse_flag_file(text, format).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// The line and message of the error from parsing `text`.
+    fn error(text: &str, format: FlagFileFormat) -> (usize, String) {
+        match parse_flag_file(text, format) {
+            Err(FlagFileError::Invalid { line, message }) => (line, message),
+            Err(e) => panic!("unexpected error: {e}"),
+            Ok(_) => panic!("parsed without an error"),
+        }
+    }
+
+    fn stripes(flag: &FlagDefinition) -> (&[u32], f32) {
+        match &flag.color_pattern {
+            ColorPattern::Stripes(stripes) => (&stripes.stripes, stripes.factor),
+            ColorPattern::Rainbow => panic!("not striped"),
+        }
+    }
+
+    #[test]
+    fn toml() {
+        let flags = parse(concat!(
+            "# a comment\n",
+            "[[flag]]\n",
+            "name = \"demisexual\" # another\n",
+            "stripes = [\"#000000\", \"#a3a3a3\",\n",
+            "  0xffffff, \"#808\"]\n",
+            "factor = 2.5\n",
+            "ansii_pattern = [233, 247, 255, 5]\n",
+            "\n",
+            "[[flag]]\n",
+            "name = \"mono\"\n",
+            "stripes = [\"#fff\"]\n",
+        ), FlagFileFormat::Toml);
+
+        assert_eq!(flags.len(), 2);
+        assert_eq!(flags[0].name, "demisexual");
+        assert_eq!(stripes(&flags[0]), (&[0x000000, 0xa3a3a3, 0xffffff, 0x880088][..], 2.5));
+        assert_eq!(*flags[0].ansii_pattern.0, [233, 247, 255, 5]);
+        assert_eq!(flags[1].name, "mono");
+        assert_eq!(stripes(&flags[1]), (&[0xffffff][..], ColorStripes::DEFAULT_FACTOR));
+    }
+
+    #[test]
+    fn json() {
+        let flags = parse(r##"[
+            {"name": "tab\tquote\"é🏳", "stripes": ["#ff0000", 255], "factor": 1},
+            {"name": "single", "stripes": ["#000"]}
+        ]"##, FlagFileFormat::Json);
+
+        assert_eq!(flags.len(), 2);
+        assert_eq!(flags[0].name, "tab\tquote\"\u{e9}\u{1f3f3}");
+        assert_eq!(stripes(&flags[0]), (&[0xff0000, 0x0000ff][..], 1.0));
+
+        // a single flag doesn't need the list
+        let flags = parse(r##"{"name": "single", "stripes": ["#000"]}"##, FlagFileFormat::Json);
+        assert_eq!(flags[0].name, "single");
+    }
+
+    #[test]
+    fn json_escapes() {
+        let flags = parse(r##"[{"name": "\/\\\b\f\n\r", "stripes": ["#000"]}]"##, FlagFileFormat::Json);
+        assert_eq!(flags[0].name, "/\\\x08\x0c\n\r");
+
+        assert_eq!(error(r##"[{"name": "\ud83c"}]"##, FlagFileFormat::Json).1, "unpaired surrogate '\\ud83c'");
+        assert_eq!(error(r##"[{"name": "\udff3"}]"##, FlagFileFormat::Json).1, "unpaired surrogate '\\udff3'");
+    }
+
+    #[test]
+    fn toml_error_lines() {
+        let flag = "[[flag]]\nname = \"x\"\nstripes = [\"#000\"]\n";
+
+        assert_eq!(error(&format!("{flag}stripes = [\"#fff\"]\n"), FlagFileFormat::Toml),
+            (4, "duplicate key 'stripes'".into()));
+        assert_eq!(error(&format!("{flag}colour = 1\n"), FlagFileFormat::Toml),
+            (4, "unknown key 'colour'".into()));
+        // errors inside a list point at the element, not the key
+        assert_eq!(error("[[flag]]\nname = \"x\"\nstripes = [\n  \"#000\",\n  \"#nope\",\n]\n", FlagFileFormat::Toml),
+            (5, "invalid color '#nope', expected '#rrggbb'".into()));
+        // a missing key is reported where its flag starts
+        assert_eq!(error(&format!("{flag}\n[[flag]]\nname = \"y\"\n"), FlagFileFormat::Toml),
+            (5, "flag is missing 'stripes'".into()));
+    }
+
+    #[test]
+    fn json_error_lines() {
+        assert_eq!(error("[\n  {\n    \"name\": \"x\",\n    \"stripes\": [\"#000\", 16777216]\n  }\n]", FlagFileFormat::Json),
+            (4, "color 0x1000000 is out of range".into()));
+        assert_eq!(error("[\n  {\n    \"stripes\": [\"#000\"]\n  }\n]", FlagFileFormat::Json).0, 2);
+    }
+
+    #[test]
+    fn format_from_path() {
+        assert_eq!(FlagFileFormat::from_path(Path::new("flags.JSON")), FlagFileFormat::Json);
+        assert_eq!(FlagFileFormat::from_path(Path::new("flags.toml")), FlagFileFormat::Toml);
+        assert_eq!(FlagFileFormat::from_path(Path::new("flags")), FlagFileFormat::Toml);
+    }
+}
@@ -0,0 +1,937 @@
+use std::borrow::Cow;
+use std::sync::{Arc, OnceLock};
+
+pub mod ansii_color;
+mod escape;
+pub mod flag_file;
+mod flags;
+mod parallel;
+mod spans;
+pub mod terminal;
+mod width;
+mod writer;
+pub use flags::FLAGS;
+pub use spans::{ColoredChars, Span, Spans};
+pub use writer::QueercatWriter;
+
+pub const ESCAPE_CHAR: char = '\x1b'; //'\033'
+
+pub struct FlagDefinition {
+    pub name: Cow<'static, str>,
+    pub ansii_pattern: ColorPattern_Ansii,
+    pub color_pattern: twenty_four_bit_color::ColorPattern,
+}
+
+// TODO? replace below struct with:
+//type ansii_pattern_t = Cow<'static, [u8]>;
+#[allow(non_camel_case_types)]
+pub struct ColorPattern_Ansii(pub Cow<'static, [u8]>);
+
+impl ColorPattern_Ansii {
+    /// Approximate 24-bit stripes with the nearest 256-color codes.
+    ///
+    /// Each stripe is doubled, like most of the hand-tuned built-in patterns.
+    pub fn from_stripes(stripes: &[u32]) -> Self {
+        let codes = stripes.iter()
+            .map(|s| ansii_color::nearest(RGBColor::from(*s)))
+            .flat_map(|code| [code, code])
+            .collect();
+        ColorPattern_Ansii(Cow::Owned(codes))
+    }
+}
+
+impl FlagDefinition {
+    /// A striped flag with a 256-color pattern derived from `stripes`.
+    ///
+    /// `None` if there are no stripes.
+    pub fn from_stripes(name: impl Into<Cow<'static, str>>, stripes: Vec<u32>, factor: f32) -> Option<Self> {
+        use twenty_four_bit_color::{ColorPattern, ColorStripes};
+        if stripes.is_empty() {
+            return None;
+        }
+        Some(FlagDefinition {
+            name: name.into(),
+            ansii_pattern: ColorPattern_Ansii::from_stripes(&stripes),
+            color_pattern: ColorPattern::Stripes(ColorStripes {
+                stripes: Cow::Owned(stripes),
+                factor,
+            }),
+        })
+    }
+}
+
+pub fn lookup_pattern(name: &str) -> Option<&'static FlagDefinition> {
+    FLAGS.iter().find(|f| f.name == name)
+        .or_else(|| {
+            let n:usize = str::parse(name).ok()
+                .filter(|n| *n < FLAGS.len())?;
+            Some(&FLAGS[n])
+        })
+}
+
+/// Like [`lookup_pattern`], but searching `flags` instead of just [`FLAGS`].
+pub fn lookup_pattern_in<'f>(flags: &[&'f FlagDefinition], name: &str) -> Option<&'f FlagDefinition> {
+    flags.iter().find(|f| f.name == name)
+        .or_else(|| {
+            let n:usize = str::parse(name).ok()
+                .filter(|n| *n < flags.len())?;
+            Some(&flags[n])
+        })
+        .copied()
+}
+
+pub mod twenty_four_bit_color {
+    use std::borrow::Cow;
+
+    pub enum ColorPattern {
+        Rainbow,
+        Stripes(ColorStripes)
+    }
+
+    impl ColorPattern {
+        pub fn get_color(&self, theta: f32) -> RGBColor {
+            use ColorPattern::*;
+            match self {
+                Rainbow =>
+                    get_color_rainbow(theta),
+                Stripes(patt) =>
+                    get_color_stripes(patt, theta),
+            }
+        }
+    }
+
+    pub struct ColorStripes {
+        pub stripes: Cow<'static, [u32]>,
+        pub factor: f32,
+    }
+
+    impl ColorStripes {
+        /// The blending factor most of the built-in flags use.
+        pub const DEFAULT_FACTOR: f32 = 4.0;
+    }
+
+    /// Parse a `#rrggbb` or `#rgb` color, with or without the `#`.
+    pub fn parse_hex_color(s: &str) -> Option<u32> {
+        let hex = s.strip_prefix('#').unwrap_or(s);
+        if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        let color = u32::from_str_radix(hex, 16).ok()?;
+        match hex.len() {
+            6 => Some(color),
+            3 => {
+                // #abc is short for #aabbcc
+                let [r, g, b] = [(color >> 8) & 0xf, (color >> 4) & 0xf, color & 0xf];
+                Some((r * 0x11) << 16 | (g * 0x11) << 8 | (b * 0x11))
+            }
+            _ => None,
+        }
+    }
+
+    #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+    pub struct RGBColor {
+        pub red: u8,
+        pub green: u8,
+        pub blue: u8,
+    }
+
+    impl From<u32> for RGBColor {
+        /// From a `0xrrggbb` value, like the ones in flag stripes.
+        fn from(color: u32) -> Self {
+            let [_, red, green, blue] = color.to_be_bytes();
+            RGBColor { red, green, blue }
+        }
+    }
+
+    fn mix_colors(color1: u32, color2: u32, balance: f32, factor: f32) -> RGBColor {
+        let balance = balance.powf(factor);
+
+        #[allow(clippy::identity_op)]
+        fn to_components(color: u32) -> [f32; 3] {
+            let red   = ((color & 0xff0000) >> 16) as f32;
+            let green = ((color & 0x00ff00) >>  8) as f32;
+            let blue  = ((color & 0x0000ff) >>  0) as f32;
+            [red, green, blue]
+        }
+
+        fn mix(c1: f32, c2: f32, balance: f32) -> u8 {
+            (c1 * balance + c2 * (1.0 - balance)).round() as u8
+        }
+
+        let [r1, g1, b1] = to_components(color1);
+        let [r2, g2, b2] = to_components(color2);
+
+        let (red, green, blue) = (
+            mix(r1, r2, balance),
+            mix(g1, g2, balance),
+            mix(b1, b2, balance),
+        );
+
+        RGBColor { red, green, blue }
+    }
+
+    fn clamp_theta(mut theta: f32) -> f32 {
+        use std::f32::consts::PI;
+        while theta < 0.0 {
+            theta += 2.0 * PI;
+        }
+        while theta >= 2.0 * PI {
+            theta -= 2.0 * PI;
+        }
+        theta
+    }
+
+    fn get_color_rainbow(theta: f32) -> RGBColor {
+        use std::f32::consts::PI;
+        let theta = clamp_theta(theta);
+
+        let gen_color_component = |offset_factor: f32| -> u8 {
+            ((1.0 * (0.5 + 0.5 * (theta + offset_factor * PI / 3.0).sin())) * 255.0).round() as u8
+        };
+
+        /* Generate the color. */
+        let red = gen_color_component(0.0);
+        let green = gen_color_component(2.0);
+        let blue = gen_color_component(4.0);
+
+        RGBColor { red, green, blue }
+    }
+
+    fn get_color_stripes(color_pattern: &ColorStripes, theta: f32) -> RGBColor {
+        use std::f32::consts::PI;
+        let theta = clamp_theta(theta);
+
+        let stripes = &color_pattern.stripes;
+        let stripe_count = stripes.len();
+
+        // TODO figure out how to calcualte this directly, w/out the loop
+        /* Find the stripe based on theta and generate the color. */
+        let stripe_size = (2.0 * PI) / stripe_count as f32;
+        for i in 0..stripe_count {
+            let min_theta = i as f32 * stripe_size;
+            let max_theta = (i + 1) as f32 * stripe_size;
+
+            if min_theta <= theta && max_theta > theta {
+                let balance = 1.0 - ((theta - min_theta) / stripe_size);
+
+                let next_color = {
+                    let next_i = i + 1;
+                    if next_i >= stripe_count {
+                        stripes[0]
+                    } else {
+                        stripes[next_i]
+                    }
+                };
+
+                return mix_colors(stripes[i], next_color, balance, color_pattern.factor);
+            }
+        }
+        panic!["never found a color"];
+    }
+}
+
+use escape::{CharKind, EscapeState, Stripper};
+use width::ClusterState;
+use twenty_four_bit_color::RGBColor;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OutputColorType {
+    /// 256 colors, stepping through the flag's hand-picked `ansii_pattern`.
+    Ansii,
+    /// 256 colors, following the 24-bit gradient as closely as the palette allows.
+    AnsiiSmooth,
+    TwentyFourBit,
+    /// The 16 basic colors (SGR 30-37 and 90-97), following the 24-bit gradient.
+    Basic16,
+    /// The 8 original colors (SGR 30-37), following the 24-bit gradient.
+    Basic8,
+}
+
+/// A single foreground color, as chosen by a [`Colorizer`].
+///
+/// The `Display` impl formats it as the SGR escape sequence that selects it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Color {
+    Ansii(u8),
+    TwentyFourBit(RGBColor),
+    Basic(BasicColor),
+}
+
+/// One of the 16 basic colors: 0-7 normal, 8-15 bright.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct BasicColor(u8);
+
+impl BasicColor {
+    /// `None` if `code` is past the 16 basic colors.
+    pub fn new(code: u8) -> Option<Self> {
+        (code < 16).then_some(BasicColor(code))
+    }
+
+    pub fn code(self) -> u8 {
+        self.0
+    }
+}
+
+impl Color {
+    /// The SGR escape sequence that selects this color.
+    fn escape(&self) -> EscapeBuf {
+        let mut buf = EscapeBuf::default();
+        buf.push(b"\x1b[");
+        match *self {
+            Color::Ansii(code) => {
+                buf.push(b"38;5;");
+                buf.push_number(code);
+            }
+            Color::TwentyFourBit(RGBColor { red, green, blue }) => {
+                buf.push(b"38;2;");
+                buf.push_number(red);
+                buf.push(b";");
+                buf.push_number(green);
+                buf.push(b";");
+                buf.push_number(blue);
+            }
+            Color::Basic(BasicColor(code @ 0..=7)) => buf.push_number(30 + code),
+            Color::Basic(BasicColor(code)) => buf.push_number(90 + (code - 8)),
+        }
+        buf.push(b"m");
+        buf
+    }
+}
+
+impl std::fmt::Display for Color {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.escape().as_str())
+    }
+}
+
+/// An escape sequence, formatted on the stack: this happens once per
+/// character of output, so it's worth skipping the `fmt` machinery.
+#[derive(Default)]
+struct EscapeBuf {
+    bytes: [u8; 19], // long enough for "\x1b[38;2;255;255;255m"
+    len: usize,
+}
+
+impl EscapeBuf {
+    fn push(&mut self, bytes: &[u8]) {
+        self.bytes[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+        self.len += bytes.len();
+    }
+
+    fn push_number(&mut self, n: u8) {
+        if n >= 100 {
+            self.push(&[b'0' + n / 100]);
+        }
+        if n >= 10 {
+            self.push(&[b'0' + n / 10 % 10]);
+        }
+        self.push(&[b'0' + n % 10]);
+    }
+
+    fn as_str(&self) -> &str {
+        std::str::from_utf8(&self.bytes[..self.len]).expect("escapes are ASCII")
+    }
+}
+
+/// Which escape sequences to remove from the input before colorizing it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum StripMode {
+    #[default]
+    Keep,
+    /// Just the SGR sequences that set colors and other text attributes.
+    Sgr,
+    /// Every escape sequence.
+    All,
+}
+
+/// Escape sequence that resets all attributes back to the terminal default.
+pub const COLOR_RESET: &str = "\x1b[0m";
+
+/// Maps text positions to colors for one flag and set of gradient options.
+#[derive(Clone)]
+pub struct Colorizer<'a> {
+    flag: &'a FlagDefinition, // private, along with color_type, so the gradient table can't go stale
+    pub horiz_freq: f32,
+    pub vert_freq: f32,
+    pub horiz_offset: f32,
+    pub rand_offset: i32,
+    color_type: OutputColorType,
+    /// Leave text alone if the input already gave it a foreground color.
+    pub preserve_colors: bool,
+    pub strip: StripMode,
+    /// When false, nothing is colored and no reset is added; combine with
+    /// `strip` to just clean escape sequences out of the text.
+    pub color_output: bool,
+    /// Columns between tab stops.
+    pub tab_size: u32,
+    /// Replace tabs with the spaces they stand for.
+    pub expand_tabs: bool,
+    /// Work out every gradient color from scratch, instead of looking it
+    /// up in a table of [`GRADIENT_STEPS`](Colorizer::GRADIENT_STEPS) colors.
+    pub exact: bool,
+    /// The gradient's colors, at evenly spaced points around it, for `flag`
+    /// and `color_type`.
+    gradient_table: OnceLock<Arc<[Color]>>,
+}
+
+impl<'a> Colorizer<'a> {
+    pub const DEFAULT_H_FREQ: f32 = 0.23;
+    pub const DEFAULT_V_FREQ: f32 = 0.1;
+    pub const DEFAULT_TAB_SIZE: u32 = 8;
+    /// How many colors the gradient table holds: enough that neighbouring
+    /// entries are next to indistinguishable.
+    pub const GRADIENT_STEPS: usize = 1024;
+
+    pub fn new(
+        flag: &'a FlagDefinition,
+        horiz_freq: f32,
+        vert_freq: f32,
+        horiz_offset: f32,
+        color_type: OutputColorType,
+    ) -> Self {
+        Colorizer {
+            flag,
+            horiz_freq,
+            vert_freq,
+            horiz_offset,
+            rand_offset: 0,
+            color_type,
+            preserve_colors: false,
+            strip: StripMode::Keep,
+            color_output: true,
+            tab_size: Self::DEFAULT_TAB_SIZE,
+            expand_tabs: false,
+            exact: false,
+            gradient_table: OnceLock::new(),
+        }
+    }
+
+    pub fn flag(&self) -> &'a FlagDefinition {
+        self.flag
+    }
+
+    pub fn color_type(&self) -> OutputColorType {
+        self.color_type
+    }
+
+    pub fn with_flag(self, flag: &'a FlagDefinition) -> Self {
+        Colorizer { flag, gradient_table: OnceLock::new(), ..self }
+    }
+
+    pub fn with_color_type(self, color_type: OutputColorType) -> Self {
+        Colorizer { color_type, gradient_table: OnceLock::new(), ..self }
+    }
+
+    pub fn with_rand_offset(self, rand_offset: i32) -> Self {
+        Colorizer { rand_offset, ..self }
+    }
+
+    pub fn with_preserve_colors(self, preserve_colors: bool) -> Self {
+        Colorizer { preserve_colors, ..self }
+    }
+
+    pub fn with_strip(self, strip: StripMode) -> Self {
+        Colorizer { strip, ..self }
+    }
+
+    pub fn with_color_output(self, color_output: bool) -> Self {
+        Colorizer { color_output, ..self }
+    }
+
+    pub fn with_tab_size(self, tab_size: u32) -> Self {
+        Colorizer { tab_size, ..self }
+    }
+
+    pub fn with_expand_tabs(self, expand_tabs: bool) -> Self {
+        Colorizer { expand_tabs, ..self }
+    }
+
+    pub fn with_exact(self, exact: bool) -> Self {
+        Colorizer { exact, ..self }
+    }
+
+    /// The color for the text at `char_index` on line `line_index`.
+    ///
+    /// `char_index` counts terminal cells, so a double-width character
+    /// moves the next one along by two.
+    pub fn color_at(&self, char_index: u32, line_index: u32) -> Color {
+        use self::OutputColorType::*;
+        use std::f32::consts::PI;
+
+        let Colorizer {
+            flag,
+            horiz_freq,
+            vert_freq,
+            horiz_offset,
+            rand_offset,
+            ..
+        } = self;
+
+        // TODO can we make this less gross?
+        let char_index_f: f32 = char_index as f32;
+        let line_index_f: f32 = line_index as f32;
+
+        if self.color_type == Ansii {
+            let pat_codes = &flag.ansii_pattern.0;
+            let pat_code_count = pat_codes.len();
+
+            let ncc = ((horiz_offset * (pat_code_count as f32)).round() as i32)
+                + ((char_index_f * horiz_freq + line_index_f * vert_freq).trunc() as i32);
+
+            let code_index = (rand_offset + ncc) as usize % pat_code_count;
+            return Color::Ansii(pat_codes[code_index]);
+        }
+
+        let theta =
+            char_index_f * horiz_freq / 5.0
+            + line_index_f * vert_freq
+            + (horiz_offset + 2.0 * *rand_offset as f32 / f32::MAX) * PI;
+
+        if self.exact {
+            return self.gradient_color(theta);
+        }
+
+        let table = self.gradient_table.get_or_init(|| self.build_gradient_table());
+        let turns = theta / (2.0 * PI);
+        let step = ((turns - turns.floor()) * Self::GRADIENT_STEPS as f32).round() as usize;
+        table[step % Self::GRADIENT_STEPS]
+    }
+
+    /// The color at `theta` along the flag's gradient, converted for `color_type`.
+    fn gradient_color(&self, theta: f32) -> Color {
+        use self::OutputColorType::*;
+
+        let color = self.flag.color_pattern.get_color(theta);
+        match self.color_type {
+            TwentyFourBit =>
+                Color::TwentyFourBit(color),
+            Ansii | AnsiiSmooth =>
+                Color::Ansii(ansii_color::nearest(color)),
+            Basic16 =>
+                Color::Basic(BasicColor(ansii_color::nearest_in(color, 0..=15))),
+            Basic8 =>
+                Color::Basic(BasicColor(ansii_color::nearest_in(color, 0..=7))),
+        }
+    }
+
+    fn build_gradient_table(&self) -> Arc<[Color]> {
+        use std::f32::consts::PI;
+
+        (0..Self::GRADIENT_STEPS)
+            .map(|step| self.gradient_color(step as f32 / Self::GRADIENT_STEPS as f32 * 2.0 * PI))
+            .collect()
+    }
+
+    /// Colorize `input` from the top-left corner, ending with a color reset.
+    pub fn colorize(&self, input: impl AsRef<str>) -> String {
+        let mut state = ColorizerState::default();
+        let mut out = String::new();
+        self.colorize_into(&mut state, input.as_ref(), &mut out);
+        self.finish_into(&mut state, &mut out);
+        out
+    }
+
+    /// Colorize `input`, continuing from (and updating) `state`, appending to `out`.
+    ///
+    /// Does not emit a trailing reset, so that a stream can be fed through in
+    /// pieces; call [`finish_into`](Colorizer::finish_into) at the end.
+    pub fn colorize_into(&self, state: &mut ColorizerState, input: &str, out: &mut String) {
+        if self.strip == StripMode::Keep {
+            self.colorize_chars(state, input.chars(), out);
+        } else {
+            let mut stripped = std::mem::take(&mut state.stripped);
+            for c in input.chars() {
+                state.line.stripper.feed(self.strip, c, &mut stripped);
+            }
+            self.colorize_chars(state, stripped.drain(..), out);
+            state.stripped = stripped;
+        }
+    }
+
+    /// End a stream fed through [`colorize_into`](Colorizer::colorize_into):
+    /// pass on anything held back, and reset the color.
+    pub fn finish_into(&self, state: &mut ColorizerState, out: &mut String) {
+        self.flush_held_into(state, out);
+        if self.color_output {
+            out.push_str(COLOR_RESET);
+        }
+    }
+
+    /// Colorize whatever the stripper was holding back, in case it turns out to be SGR.
+    pub(crate) fn flush_held_into(&self, state: &mut ColorizerState, out: &mut String) {
+        let mut held = String::new();
+        state.line.stripper.flush(&mut held);
+        self.colorize_chars(state, held.chars(), out);
+    }
+
+    fn colorize_chars(&self, state: &mut ColorizerState, input: impl Iterator<Item = char>, out: &mut String) {
+        for current_char in input {
+            match self.step(state, current_char) {
+                // whitespace looks the same whatever color it is
+                Step::Text(color) if current_char.is_whitespace() || state.current_color == Some(color) => {
+                    out.push(current_char);
+                }
+                Step::Text(color) => {
+                    out.push_str(color.escape().as_str());
+                    out.push(current_char);
+                    state.current_color = Some(color);
+                }
+                Step::Plain | Step::Joined(_) => {
+                    out.push(current_char);
+                }
+                Step::Spaces(count) => {
+                    out.extend(std::iter::repeat_n(' ', count as usize));
+                }
+            }
+        }
+    }
+
+    /// Iterate over `input`, pairing each character with its color.
+    ///
+    /// Escape sequences are stripped first, according to `strip`.
+    ///
+    /// Characters that should be left as they are get `None`: control
+    /// characters, escape sequences, and (with `preserve_colors`) text the
+    /// input colored itself. With `expand_tabs`, tabs come out as spaces.
+    pub fn colored_chars<I>(&self, input: I) -> ColoredChars<'_, I::IntoIter>
+        where I: IntoIterator<Item = char>
+    {
+        ColoredChars::new(self, input.into_iter())
+    }
+
+    /// Iterate over `input` as runs of consecutive characters sharing a color.
+    pub fn spans<I>(&self, input: I) -> Spans<'_, I::IntoIter>
+        where I: IntoIterator<Item = char>
+    {
+        Spans::new(self.colored_chars(input))
+    }
+
+    fn step(&self, state: &mut ColorizerState, current_char: char) -> Step {
+        let line_index = state.line.line_index; // before any newline moves it on
+        let kind = state.line.advance(current_char);
+
+        // a cluster's color comes from the column it starts at
+        let starts_cluster = if kind == CharKind::Printable {
+            match state.cluster.add(current_char) {
+                Some(advance) => {
+                    state.column += advance;
+                    true
+                }
+                None => false,
+            }
+        } else {
+            state.column += state.cluster.finish();
+            false
+        };
+
+        if current_char == '\t' && kind == CharKind::Control {
+            let tab_size = self.tab_size.max(1);
+            let stop = (state.column / tab_size + 1) * tab_size;
+            let width = stop - state.column;
+            state.column = stop;
+            return if self.expand_tabs { Step::Spaces(width) } else { Step::Plain };
+        }
+
+        let colored_by_input = self.preserve_colors && state.line.input_foreground;
+
+        let step = match kind {
+            _ if !self.color_output =>
+                Step::Plain,
+            CharKind::Printable if colored_by_input =>
+                Step::Plain,
+            CharKind::Printable if starts_cluster =>
+                Step::Text(self.color_at(state.column, line_index)),
+            CharKind::Printable =>
+                Step::Joined(self.color_at(state.column, line_index)),
+            CharKind::Control =>
+                Step::Plain,
+            CharKind::InSequence | CharKind::SequenceEnd => {
+                // the sequence may change the color, so ours will need setting again
+                state.current_color = None;
+                Step::Plain
+            }
+        };
+
+        // only printable text takes up room on screen; escape sequences
+        // and other control characters don't move the column along, except
+        // for the ones that move the cursor back
+        match current_char {
+            '\n' | '\r' => state.column = 0,
+            '\x08' => state.column = state.column.saturating_sub(1),
+            _ => {}
+        }
+
+        step
+    }
+}
+
+/// What to do with one input character.
+enum Step {
+    /// Printable, and the start of a grapheme cluster: color it.
+    Text(Color),
+    /// Printable, but part of the cluster before it, so already colored.
+    Joined(Color),
+    /// Pass it through as is: part of an escape sequence, a control
+    /// character, or text the input colored itself.
+    Plain,
+    /// A tab being expanded: replace it with this many spaces.
+    Spaces(u32),
+}
+
+/// Position and escape-sequence tracking for a stream being colorized.
+#[derive(Clone, Default)]
+pub struct ColorizerState {
+    line: LineState,
+    column: u32, // in terminal cells, as of the start of the current cluster
+    cluster: ClusterState,
+    current_color: Option<Color>, // the last one we set, if it's still in effect
+    stripped: String, // scratch space for stripper output
+}
+
+impl ColorizerState {
+    /// Start at the beginning of a line, in the middle of the input.
+    fn at_line_start(line: LineState) -> Self {
+        ColorizerState { line, ..Default::default() }
+    }
+}
+
+/// The part of [`ColorizerState`] that carries over from one line to the
+/// next. Everything else starts afresh with each line, so this is all it
+/// takes to pick up colorizing from the start of any line.
+#[derive(Clone, Default)]
+struct LineState {
+    line_index: u32,
+    escape_state: EscapeState,
+    csi_params: String, // of the control sequence being read, if any
+    input_foreground: bool, // whether the input has set its own foreground color
+    stripper: Stripper,
+}
+
+impl LineState {
+    /// Move past `c`, and say what kind of character it is.
+    fn advance(&mut self, c: char) -> CharKind {
+        let prev = self.escape_state;
+        let kind = self.escape_state.advance(c);
+        self.track_sgr(prev, kind, c);
+        if c == '\n' {
+            self.line_index += 1;
+        }
+        kind
+    }
+
+    /// Keep track of the input's foreground color, as set by its SGR sequences.
+    fn track_sgr(&mut self, prev: EscapeState, kind: CharKind, c: char) {
+        match (prev, self.escape_state, kind) {
+            (EscapeState::Csi, EscapeState::Csi, _) => self.csi_params.push(c),
+            (_, EscapeState::Csi, _) => self.csi_params.clear(),
+            (EscapeState::Csi, _, CharKind::SequenceEnd) if c == 'm' => {
+                self.input_foreground = escape::sgr_sets_foreground(&self.csi_params, self.input_foreground);
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use twenty_four_bit_color::ColorStripes;
+
+    #[test]
+    fn color_escapes() {
+        let basic = |code| Color::Basic(BasicColor::new(code).expect("basic color"));
+        assert_eq!(basic(0).to_string(), "\x1b[30m");
+        assert_eq!(basic(7).to_string(), "\x1b[37m");
+        assert_eq!(basic(8).to_string(), "\x1b[90m");
+        assert_eq!(basic(15).to_string(), "\x1b[97m");
+        assert_eq!(Color::Ansii(255).to_string(), "\x1b[38;5;255m");
+        assert_eq!(Color::TwentyFourBit(RGBColor { red: 255, green: 0, blue: 128 }).to_string(), "\x1b[38;2;255;0;128m");
+    }
+
+    #[test]
+    fn flag_from_stripes() {
+        assert!(FlagDefinition::from_stripes("empty", vec![], ColorStripes::DEFAULT_FACTOR).is_none());
+
+        let flag = FlagDefinition::from_stripes("mono", vec![0xff0000], ColorStripes::DEFAULT_FACTOR).expect("one stripe");
+        assert_eq!(*flag.ansii_pattern.0, [196, 196]);
+        for color_type in [OutputColorType::Ansii, OutputColorType::TwentyFourBit, OutputColorType::Basic8] {
+            let colorizer = Colorizer::new(&flag, 0.23, 0.1, 0.0, color_type);
+            assert!(colorizer.colorize("red").contains('r'));
+        }
+    }
+
+    #[test]
+    fn basic_colors_stop_at_15() {
+        assert_eq!(BasicColor::new(15).map(BasicColor::code), Some(15));
+        assert_eq!(BasicColor::new(16), None);
+        assert_eq!(BasicColor::new(255), None);
+    }
+
+    fn rainbow(color_type: OutputColorType) -> Colorizer<'static> {
+        Colorizer::new(lookup_pattern("rainbow").expect("built-in flag"), 0.23, 0.1, 0.0, color_type)
+    }
+
+    /// Each printable character of colorized `output`, with the color we last set before it.
+    fn rendered(output: &str) -> Vec<(char, &str)> {
+        let mut state = EscapeState::Ground;
+        let mut chars = Vec::new();
+        let mut color = "";
+        let mut sequence_start = 0;
+        for (i, c) in output.char_indices() {
+            match state.advance(c) {
+                CharKind::Printable => chars.push((c, color)),
+                CharKind::InSequence if c == ESCAPE_CHAR => sequence_start = i,
+                CharKind::SequenceEnd if output[sequence_start..].starts_with("\x1b[38;") => {
+                    color = &output[sequence_start..=i];
+                }
+                _ => {}
+            }
+        }
+        chars
+    }
+
+    /// The color of each of `input`'s characters, in order.
+    fn colors(colorizer: &Colorizer, input: &str) -> Vec<Option<Color>> {
+        colorizer.colored_chars(input.chars()).map(|(_, color)| color).collect()
+    }
+
+    #[test]
+    fn input_escapes_keep_the_stripes_in_place() {
+        let colorizer = rainbow(OutputColorType::TwentyFourBit);
+        let plain = colorizer.colorize("hello world\nsecond line");
+        let styled = colorizer.colorize("\x1b[1mhello\x1b[0m world\n\x1b[4;31msecond\x1b[0m line");
+        assert_eq!(rendered(&styled), rendered(&plain));
+        assert_eq!(rendered(&plain).len(), "hello worldsecond line".len());
+    }
+
+    #[test]
+    fn carriage_return_and_backspace_move_back() {
+        let colorizer = rainbow(OutputColorType::TwentyFourBit);
+
+        // overwriting the start of the line, like a progress bar
+        let overwritten = colors(&colorizer, "abc\rxyz");
+        assert_ne!(overwritten[0], overwritten[1]);
+        assert_eq!(overwritten[4..], overwritten[..3]);
+
+        // "c" goes where "b" was
+        let backspaced = colors(&colorizer, "ab\x08c");
+        assert_eq!(backspaced[3], backspaced[1]);
+        // but nothing goes back past the start of the line
+        let backspaced = colors(&colorizer, "\x08\x08a");
+        assert_eq!(backspaced[2], Some(colorizer.color_at(0, 0)));
+    }
+
+    /// The text `colored_chars` gives for `input`, and the color of the first `x` in it.
+    fn expanded(colorizer: &Colorizer, input: &str) -> (String, Option<Color>) {
+        let chars: Vec<_> = colorizer.colored_chars(input.chars()).collect();
+        let x = chars.iter().find(|(c, _)| *c == 'x').and_then(|(_, color)| *color);
+        (chars.into_iter().map(|(c, _)| c).collect(), x)
+    }
+
+    #[test]
+    fn tab_stops() {
+        let colorizer = rainbow(OutputColorType::TwentyFourBit);
+        let at = |column| Some(colorizer.color_at(column, 0));
+
+        // at column 0, part way to a stop, and right on one
+        assert_eq!(expanded(&colorizer, "\tx"), ("\tx".into(), at(8)));
+        assert_eq!(expanded(&colorizer, "abc\tx"), ("abc\tx".into(), at(8)));
+        assert_eq!(expanded(&colorizer, "abcdefgh\tx"), ("abcdefgh\tx".into(), at(16)));
+        // wide characters count as two columns
+        assert_eq!(expanded(&colorizer, "\u{4e2d}\u{6587}abc\tx"), ("\u{4e2d}\u{6587}abc\tx".into(), at(8)));
+
+        let colorizer = colorizer.clone().with_tab_size(1);
+        let at = |column| Some(colorizer.color_at(column, 0));
+        assert_eq!(expanded(&colorizer, "\tx"), ("\tx".into(), at(1)));
+        assert_eq!(expanded(&colorizer, "ab\t\tx"), ("ab\t\tx".into(), at(4)));
+    }
+
+    #[test]
+    fn expand_tabs() {
+        let colorizer = rainbow(OutputColorType::TwentyFourBit).with_expand_tabs(true);
+        let at = |column| Some(colorizer.color_at(column, 0));
+
+        assert_eq!(expanded(&colorizer, "\tx"), (format!("{}x", " ".repeat(8)), at(8)));
+        assert_eq!(expanded(&colorizer, "abc\tx"), (format!("abc{}x", " ".repeat(5)), at(8)));
+        assert_eq!(expanded(&colorizer, "abcdefgh\tx"), (format!("abcdefgh{}x", " ".repeat(8)), at(16)));
+        // each line starts again at column 0
+        assert_eq!(expanded(&colorizer, "abc\n\tx").0, format!("abc\n{}x", " ".repeat(8)));
+
+        let colorizer = colorizer.clone().with_tab_size(1);
+        let at = |column| Some(colorizer.color_at(column, 0));
+        assert_eq!(expanded(&colorizer, "\tx"), (" x".into(), at(1)));
+        assert_eq!(expanded(&colorizer, "ab\t\tx"), ("ab  x".into(), at(4)));
+
+        // and the same spaces come out of colorize
+        let output = colorizer.with_tab_size(8).colorize("abc\tx");
+        assert_eq!(rendered(&output).into_iter().map(|(c, _)| c).collect::<String>(), format!("abc{}x", " ".repeat(5)));
+    }
+
+    #[test]
+    fn color_escapes_only_when_needed() {
+        // each code is doubled, so at one column per code, pairs of
+        // characters share a color
+        let flag = FlagDefinition::from_stripes("red-blue", vec![0xff0000, 0x0000ff], ColorStripes::DEFAULT_FACTOR)
+            .expect("two stripes");
+        assert_eq!(*flag.ansii_pattern.0, [196, 196, 21, 21]);
+        let colorizer = Colorizer::new(&flag, 1.0, 0.0, 0.0, OutputColorType::Ansii);
+        let (red, blue) = ("\x1b[38;5;196m", "\x1b[38;5;21m");
+
+        assert_eq!(colorizer.colorize("abcde"), format!("{red}ab{blue}cd{red}e{COLOR_RESET}"));
+        // whitespace gets no color of its own, even where the color changes
+        assert_eq!(colorizer.colorize("a  b"), format!("{red}a  {blue}b{COLOR_RESET}"));
+        // the input's own escapes might change the color, so it's set again after them
+        assert_eq!(colorizer.colorize("a\x1b[1mb"), format!("{red}a\x1b[1m{red}b{COLOR_RESET}"));
+    }
+
+    #[test]
+    fn preserve_colors() {
+        let colorizer = rainbow(OutputColorType::TwentyFourBit).with_preserve_colors(true);
+        let input = "a\x1b[31mbc\x1b[0mde\x1b[32mf\x1b[39mg";
+        let colored: String = colorizer.colored_chars(input.chars())
+            .filter_map(|(c, color)| color.map(|_| c))
+            .collect();
+        // text after a reset, or a default foreground, is ours to color again
+        assert_eq!(colored, "adeg");
+
+        let output = colorizer.colorize(input);
+        assert!(output.contains("\x1b[31mbc\x1b[0m"));
+        assert!(output.contains("\x1b[32mf\x1b[39m"));
+
+        // without it, the input's colors get overridden
+        let colorizer = colorizer.with_preserve_colors(false);
+        let colored: String = colorizer.colored_chars(input.chars())
+            .filter_map(|(c, color)| color.map(|_| c))
+            .collect();
+        assert_eq!(colored, "abcdefg");
+    }
+
+    #[test]
+    fn gradient_table_matches_exact_colors() {
+        let channels = |color| match color {
+            Color::TwentyFourBit(RGBColor { red, green, blue }) => [red, green, blue].map(i32::from),
+            other => panic!("expected a 24-bit color, not {other:?}"),
+        };
+        let difference = |a, b| channels(a).into_iter().zip(channels(b)).map(|(a, b)| (a - b).abs()).max().unwrap_or(0);
+
+        for name in ["rainbow", "transgender", "nonbinary"] {
+            let flag = lookup_pattern(name).expect("built-in flag");
+            let approximate = Colorizer::new(flag, 0.23, 0.1, 0.3, OutputColorType::TwentyFourBit).with_rand_offset(12345);
+            let exact = approximate.clone().with_exact(true);
+
+            // looking up the nearest entry is off by at most half a step,
+            // so never further out than neighbouring entries are from each other
+            let table = approximate.build_gradient_table();
+            let max_step = table.iter().zip(table.iter().cycle().skip(1))
+                .map(|(a, b)| difference(*a, *b))
+                .max().unwrap_or(0);
+
+            for line in 0..50 {
+                for column in 0..300 {
+                    let (a, b) = (approximate.color_at(column, line), exact.color_at(column, line));
+                    assert!(difference(a, b) <= max_step, "{name} at line {line}, column {column}: {a:?} vs {b:?}");
+                }
+            }
+        }
+    }
+}
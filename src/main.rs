@@ -1,33 +1,29 @@
-mod flags;
-use flags::FLAGS;
-
-const ESCAPE_CHAR: char = '\x1b'; //'\033'
-
-struct FlagDefinition {
-    name: &'static str,
-    ansii_pattern: ColorPattern_Ansii,
-    color_pattern: twenty_four_bit_color::ColorPattern,
-}
-
-// TODO? replace below struct with:
-//type ansii_pattern_t = &'static [u8];
-struct ColorPattern_Ansii(&'static [u8]);
-
-fn lookup_pattern(name: &str) -> Option<&'static FlagDefinition> {
-    FLAGS.iter().find(|f| f.name == name)
-        .or_else(|| {
-            let n:usize = str::parse(name).ok()
-                .filter(|n| *n < FLAGS.len())?;
-            Some(&FLAGS[n])
-        })
+use std::io::{self, BufRead, BufReader, BufWriter, IsTerminal, Read, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use queercat_rust::{
+    flag_file::{library_dirs, load_flag_dir, load_flag_file, FlagFileError},
+    lookup_pattern_in, terminal::detect_color_type, Colorizer, StripMode, FlagDefinition, OutputColorType, QueercatWriter, FLAGS,
+    twenty_four_bit_color::{parse_hex_color, ColorStripes},
+};
+
+/// Print a message to stderr, prefixed with the program name.
+///
+/// Unlike `eprintln!`, this doesn't panic if stderr has gone away (e.g. it
+/// was piped to something that's already exited).
+macro_rules! report {
+    ($($arg:tt)*) => {{
+        let _ = writeln!(io::stderr(), "queercat: {}", format_args!($($arg)*));
+    }};
 }
 
-fn print_version() {
-    println!("queercat-rust version 1.0, (c) 2023 solarshado");
+fn print_version() -> io::Result<()> {
+    writeln!(io::stdout(), "queercat-rust version 1.0, (c) 2023 solarshado")
 }
 
 //fn build_helpstr() -> &'static str
-fn build_helpstr() -> String {
+fn build_helpstr(flags: &[KnownFlag]) -> String {
     //
     // consider instead:
     // https://stackoverflow.com/a/32956193/
@@ -47,15 +43,42 @@ fn build_helpstr() -> String {
     let helpstr_indent = "                                      ";
 
     let helpstr_tail = concat![
+        "                --colors <c,c,...>: Use a one-off flag made of these colors,\n",
+        "                                    given as #rrggbb or #rgb\n",
+        "                --flag-file <path>: Load more flags from a TOML or JSON file\n",
         "--horizontal-frequency <d>, -h <d>: Horizontal rainbow frequency (default: 0.23)\n",
         "  --vertical-frequency <d>, -v <d>: Vertical rainbow frequency (default: 0.1)\n",
         "              --offset <d>, -o <d>: Offset of the start of the flag\n",
-        "                 --force-color, -F: Force color even when stdout is not a tty\n",
+        "                  --color[=<when>]: Color the output 'always', 'never', or 'auto'\n",
+        "                                    (default: auto, meaning when stdout is a tty,\n",
+        "                                    unless NO_COLOR, CLICOLOR_FORCE or CLICOLOR\n",
+        "                                    say otherwise; just --color means always)\n",
+        "                 --force-color, -F: Same as --color=always\n",
 //        "             --no-force-locale, -l: Use encoding from system locale instead of\n",
 //        "                                    assuming UTF-8\n",
         "                      --random, -r: Random colors\n",
-        "                       --24bit, -b: Output in 24-bit \"true\" RGB mode (slower and\n",
-        "                                    not supported by all terminals)\n",
+        "                       --24bit, -b: Output in 24-bit \"true\" RGB mode (not supported\n",
+        "                                    by all terminals)\n",
+        "                 --color-depth <d>: Number of colors to output: 8, 16, 256 or 24bit\n",
+        "                                    (default: auto, detected from the terminal)\n",
+        "                       --256smooth: Output in 256-color mode, but follow the 24-bit\n",
+        "                                    gradient instead of a fixed pattern\n",
+        "                    --exact-colors: Work out each gradient color exactly, instead\n",
+        "                                    of from a table of 1024 steps (slower)\n",
+        "                 --preserve-colors: Leave text alone if the input already colored it\n",
+        "            --strip-ansi[=sgr|all]: Remove color codes (or, with 'all', every escape\n",
+        "                                    sequence) from the input before coloring it\n",
+        "                      --strip-only: Just remove escape sequences, and don't color\n",
+        "                                    anything (default: --strip-ansi=all)\n",
+        "                     --tabsize <n>: Columns between tab stops (default: 8)\n",
+        "                     --expand-tabs: Replace tabs with spaces\n",
+        "                 --replace-invalid: Replace input that isn't valid UTF-8 with U+FFFD\n",
+        "                                    (default: pass it through uncolored)\n",
+        "                --jobs <n>, -j <n>: Colorize big files on up to n threads at once\n",
+        "                                    (default: one per CPU)\n",
+        "                   --binary=<what>: What to do with binary files: copy them as is\n",
+        "                                    ('passthrough', the default), 'skip' them,\n",
+        "                                    or 'colorize' them like text\n",
         "                         --version: Print version and exit\n",
         "                            --help: Show this message\n",
         "\n",
@@ -80,232 +103,164 @@ fn build_helpstr() -> String {
      */
 
     let helpstr_flag_list =
-        FLAGS.iter().enumerate()
-        .map(|(i,e)| format!("{helpstr_indent}{0}: {i}\n",e.name))
+        flags.iter().enumerate()
+        .map(|(i,e)| match &e.origin {
+            None => format!("{helpstr_indent}{0}: {i}\n",e.def.name),
+            Some(path) => format!("{helpstr_indent}{0}: {i} (from {1})\n",e.def.name,path.display()),
+        })
         .collect::<String>();
 
     format!["{}{}{}", helpstr_head, helpstr_flag_list, helpstr_tail]
 }
 
-mod twenty_four_bit_color {
-
-    pub(super) enum ColorPattern {
-        Rainbow,
-        Stripes(ColorStripes)
-    }
-
-    impl ColorPattern {
-        pub(super) fn get_color(&self, theta: f32) -> RGBColor {
-            use ColorPattern::*;
-            match self {
-                Rainbow =>
-                    get_color_rainbow(theta),
-                Stripes(patt) =>
-                    get_color_stripes(patt, theta),
-            }
-        }
-    }
-
-    pub(super) struct ColorStripes {
-        pub stripes: &'static [u32],
-        pub factor: f32,
-    }
-
-    pub(super) struct RGBColor {
-        pub red: u8,
-        pub green: u8,
-        pub blue: u8,
-    }
-
-    fn mix_colors(color1: u32, color2: u32, balance: f32, factor: f32) -> RGBColor {
-        let balance = balance.powf(factor);
-
-        #[allow(clippy::identity_op)]
-        fn to_components(color: u32) -> [f32; 3] {
-            let red   = ((color & 0xff0000) >> 16) as f32;
-            let green = ((color & 0x00ff00) >>  8) as f32;
-            let blue  = ((color & 0x0000ff) >>  0) as f32;
-            [red, green, blue]
-        }
-
-        fn mix(c1: f32, c2: f32, balance: f32) -> u8 {
-            (c1 * balance + c2 * (1.0 - balance)).round() as u8
-        }
-
-        let [r1, g1, b1] = to_components(color1);
-        let [r2, g2, b2] = to_components(color2);
-
-        let (red, green, blue) = (
-            mix(r1, r2, balance),
-            mix(g1, g2, balance),
-            mix(b1, b2, balance),
-        );
-
-        RGBColor { red, green, blue }
-    }
-
-    fn clamp_theta(mut theta: f32) -> f32 {
-        use std::f32::consts::PI;
-        while theta < 0.0 {
-            theta += 2.0 * PI;
-        }
-        while theta >= 2.0 * PI {
-            theta -= 2.0 * PI;
-        }
-        theta
-    }
-
-    fn get_color_rainbow(theta: f32) -> RGBColor {
-        use std::f32::consts::PI;
-        let theta = clamp_theta(theta);
-
-        let gen_color_component = |offset_factor: f32| -> u8 {
-            ((1.0 * (0.5 + 0.5 * (theta + offset_factor * PI / 3.0).sin())) * 255.0).round() as u8
-        };
-
-        /* Generate the color. */
-        let red = gen_color_component(0.0);
-        let green = gen_color_component(2.0);
-        let blue = gen_color_component(4.0);
-
-        RGBColor { red, green, blue }
-    }
-
-    fn get_color_stripes(color_pattern: &ColorStripes, theta: f32) -> RGBColor {
-        use std::f32::consts::PI;
-        let theta = clamp_theta(theta);
-
-        let stripes = color_pattern.stripes;
-        let stripe_count = stripes.len();
-
-        // TODO figure out how to calcualte this directly, w/out the loop
-        /* Find the stripe based on theta and generate the color. */
-        let stripe_size = (2.0 * PI) / stripe_count as f32;
-        for i in 0..stripe_count {
-            let min_theta = i as f32 * stripe_size;
-            let max_theta = (i + 1) as f32 * stripe_size;
-
-            if min_theta <= theta && max_theta > theta {
-                let balance = 1.0 - ((theta - min_theta) / stripe_size);
-
-                let next_color = {
-                    let next_i = i + 1;
-                    if next_i >= stripe_count {
-                        stripes[0]
-                    } else {
-                        stripes[next_i]
-                    }
-                };
-
-                return mix_colors(stripes[i], next_color, balance, color_pattern.factor);
-            }
-        }
-        panic!["never found a color"];
-    }
+// probably good enough?
+fn get_fake_random() -> u32 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().subsec_millis()
 }
 
-fn print_color(settings: &Settings, char_index: u32, line_index: u32, rand_offset: i32) {
-    use self::OutputColorType::*;
-    use std::f32::{consts::PI, MAX as f32MAX};
-
-    let Settings {
-        flag,
-        horiz_freq,
-        vert_freq,
-        horiz_offset,
-        ..
-    } = settings;
-
-    // TODO can we make this less gross?
-    let char_index_f: f32 = char_index as f32;
-    let line_index_f: f32 = line_index as f32;
-
-    match settings.color_type {
-        TwentyFourBit => {
-            let theta =
-                char_index_f * horiz_freq / 5.0
-                + line_index_f * vert_freq
-                + (horiz_offset + 2.0 * rand_offset as f32 / f32MAX) * PI;
-
-            let color = flag.color_pattern.get_color(theta);
-
-            print!("{}[38;2;{};{};{}m", ESCAPE_CHAR, color.red, color.green, color.blue);
-        },
-
-        Ansii => {
-            let pat_codes = flag.ansii_pattern.0;
-            let pat_code_count = pat_codes.len();
+enum ParseArgsFail {
+    PrintUsage(String),
+    PrintVersion,
+    BadFlagFile(String, FlagFileError),
+}
 
-            let ncc = ((horiz_offset * (pat_code_count as f32)).round() as i32)
-                + ((char_index_f * horiz_freq + line_index_f * vert_freq).trunc() as i32);
+enum ColorWhen {
+    Auto,
+    Always,
+    Never,
+}
 
-            let code_index = (rand_offset + ncc) as usize % pat_code_count;
-            print!("{}[38;5;{}m", ESCAPE_CHAR, pat_codes[code_index]);
-        }
+/// Whether the environment asks for color to be forced on or off.
+///
+/// `NO_COLOR` (<https://no-color.org/>) wins over `CLICOLOR_FORCE`, which wins
+/// over `CLICOLOR` (<https://bixense.com/clicolors/>). All of them are
+/// overridden by `--color` or `-F` on the command line.
+fn color_from_env() -> Option<bool> {
+    let var = |name| std::env::var_os(name).filter(|v| !v.is_empty());
+
+    if var("NO_COLOR").is_some() {
+        Some(false)
+    } else if var("CLICOLOR_FORCE").is_some_and(|v| v != "0") {
+        Some(true)
+    } else if var("CLICOLOR").is_some_and(|v| v == "0") {
+        Some(false)
+    } else {
+        None
     }
 }
 
-#[derive(PartialEq)]
-enum EscapeState {
-    Out,
-    In,
-    Last
+/// What to do with input that looks like a binary file.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum BinaryPolicy {
+    Passthrough,
+    Skip,
+    Colorize,
 }
 
-// TODO rewrite to return instead of use &mut
-fn find_escape_sequences(current_char: char, state: &mut EscapeState) {
-    if current_char == ESCAPE_CHAR {
-        *state = EscapeState::In;
-    } else if *state == EscapeState::In {
-        *state = if current_char.is_ascii_alphabetic() {
-            EscapeState::Last
-        } else {
-            EscapeState::In
-        };
-    } else {
-        *state = EscapeState::Out;
+/// Whether the start of a file looks like binary data rather than text,
+/// along the lines of grep's heuristic: it has a NUL byte, or too many
+/// control characters that text wouldn't have.
+fn looks_binary(block: &[u8]) -> bool {
+    const MAX_CONTROL_RATIO: usize = 10; // percent
+
+    if block.contains(&0) {
+        return true;
     }
+    let is_text_control = |b: u8| matches!(b, b'\t' | b'\n' | b'\r' | b'\x0c' | b'\x08' | b'\x1b' | b'\x07');
+    let controls = block.iter().filter(|&&b| b.is_ascii_control() && !is_text_control(b)).count();
+    controls * 100 > block.len() * MAX_CONTROL_RATIO
 }
 
-// probably good enough?
-fn get_fake_random() -> u32 {
-    use std::time::{SystemTime, UNIX_EPOCH};
-    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().subsec_millis()
+/// Which flag to use, as given on the command line.
+enum FlagChoice {
+    Named(String, String), // option, name
+    Colors(FlagDefinition), // made from --colors
 }
 
-enum ParseArgsFail {
-    PrintUsage(String),
-    PrintVersion,
+/// A flag that can be chosen with -f, and where it came from.
+struct KnownFlag {
+    def: &'static FlagDefinition,
+    origin: Option<PathBuf>, // None for built-in flags
 }
 
 struct Settings {
     file_names: Vec<String>, // "-" means "stdin"; default ["-"]
     flag: &'static FlagDefinition, // default flags[0] (rainbow)
+    known_flags: Vec<KnownFlag>, // built-in FLAGS, then any loaded from files
     horiz_freq: f32, // default 0.23
     vert_freq: f32, // default 0.1
     horiz_offset: f32, // default from (time_of_day.now.sec) % 300 /300 (?)
-    enable_color: bool, // default from is_a_tty(stdout)
+    enable_color: bool, // default from is_a_tty(stdout), then env vars
 //    force_locale: bool, // default true
-    color_type: OutputColorType, // default ansii, flag for 24bit
+    color_type: OutputColorType, // default detected from the terminal, else ansii
     enable_rand_offset: bool,
+    preserve_colors: bool, // default false
+    strip: StripMode, // default keep; all, if only stripping
+    strip_only: bool, // default false
+    tab_size: u32, // default 8
+    expand_tabs: bool, // default false
+    replace_invalid: bool, // default false
+    exact_colors: bool, // default false
+    binary: BinaryPolicy, // default passthrough
+    jobs: usize, // default the number of CPUs
     print_help: bool, // default false, ignores file_names if true
 }
 
 impl Settings {
     const DEFAULT_FLAG_INDEX: usize = 0;
-    const DEFAULT_H_FREQ: f32 = 0.23;
-    const DEFAULT_V_FREQ: f32 = 0.1;
+    const DEFAULT_H_FREQ: f32 = Colorizer::DEFAULT_H_FREQ;
+    const DEFAULT_V_FREQ: f32 = Colorizer::DEFAULT_V_FREQ;
     const DEFAULT_COLOR_TYPE: OutputColorType = OutputColorType::Ansii;
     const DEFAULT_ENABLE_RAND_OFFSET: bool = false;
+
+    fn colorizer(&self, rand_offset: i32) -> Colorizer<'static> {
+        Colorizer::new(
+            self.flag,
+            self.horiz_freq,
+            self.vert_freq,
+            self.horiz_offset,
+            self.color_type,
+        ).with_rand_offset(rand_offset)
+        .with_preserve_colors(self.preserve_colors)
+        .with_strip(self.strip)
+        .with_color_output(self.enable_color && !self.strip_only)
+        .with_tab_size(self.tab_size)
+        .with_expand_tabs(self.expand_tabs)
+        .with_exact(self.exact_colors)
+    }
+
+    /// Whether input can be copied to the output untouched.
+    fn passthrough(&self) -> bool {
+        !self.enable_color && self.strip == StripMode::Keep && !self.expand_tabs && !self.replace_invalid
+    }
+
+    fn add_flags(&mut self, loaded: Vec<FlagDefinition>, origin: PathBuf) {
+        // these live for the rest of the run anyway
+        let loaded = Box::leak(loaded.into_boxed_slice());
+        for def in loaded.iter() {
+            // -f takes the first flag with a name, so a later one can only be chosen by number
+            if let Some(earlier) = self.known_flags.iter().find(|f| f.def.name == def.name) {
+                let earlier = match &earlier.origin {
+                    None => "a built-in flag".to_owned(),
+                    Some(path) => format!["the one from {}", path.display()],
+                };
+                report!("warning: {}: flag '{}' has the same name as {earlier}; use -f {} to choose it",
+                    origin.display(), def.name, self.known_flags.len());
+            }
+            self.known_flags.push(KnownFlag { def, origin: Some(origin.clone()) });
+        }
+    }
 }
 
 impl Default for Settings {
     fn default() -> Self {
-        use std::io::{stdout, IsTerminal};
+        use std::io::stdout;
         let color_default = stdout().is_terminal();
         Settings {
             file_names: Vec::new(),
             flag: &FLAGS[Settings::DEFAULT_FLAG_INDEX],
+            known_flags: FLAGS.iter().map(|def| KnownFlag { def, origin: None }).collect(),
             horiz_freq: Settings::DEFAULT_H_FREQ,
             vert_freq: Settings::DEFAULT_V_FREQ,
 
@@ -318,18 +273,23 @@ impl Default for Settings {
 //            force_locale: true,
             color_type: Settings::DEFAULT_COLOR_TYPE,
             enable_rand_offset: Settings::DEFAULT_ENABLE_RAND_OFFSET,
+            preserve_colors: false,
+            strip: StripMode::Keep,
+            strip_only: false,
+            tab_size: Colorizer::DEFAULT_TAB_SIZE,
+            expand_tabs: false,
+            replace_invalid: false,
+            exact_colors: false,
+            binary: BinaryPolicy::Passthrough,
+            jobs: std::thread::available_parallelism().map_or(1, |n| n.get()),
             print_help: false,
         }
     }
 }
 
-enum OutputColorType {
-    Ansii,
-    TwentyFourBit,
-}
-
 fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Settings, ParseArgsFail> {
     let _ = args.next(); // discard exename in first element
+    let mut inline_value: Option<(String, String)> = None; // from --opt=value
 
     macro_rules! usage {
         ($($i:tt)*) => {
@@ -338,7 +298,8 @@ fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Settings, ParseA
     }
     macro_rules! next_arg_for {
         ($flag:ident) => {
-            args.next().ok_or(usage!["'{}' option requires an argument!", $flag])
+            inline_value.take().map(|(_, value)| value).or_else(|| args.next())
+                .ok_or(usage!["'{}' option requires an argument!", $flag])
         };
     }
     macro_rules! badval {
@@ -348,19 +309,45 @@ fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Settings, ParseA
     }
 
     let mut settings = Settings::default();
+    let mut flag_choice = None;
+    let mut flag_files = Vec::new();
+    let mut color_type = None; // auto
+    let mut color_when = ColorWhen::Auto;
+    let mut strip = None;
 
-    // TODO support -o=val / --opt=value format
+    // TODO support -o=val format
     // _maybe_ "-hvof 1 2 3 4" clustering too? sounds way harder
     //      but maybe could pre-process?
 
     while let Some(arg) = args.next() {
         use ParseArgsFail::*;
+
+        let arg = match arg.split_once('=') {
+            Some((opt, value)) if opt.starts_with("--") && opt.len() > 2 => {
+                inline_value = Some((opt.to_owned(), value.to_owned()));
+                opt.to_owned()
+            }
+            _ => arg,
+        };
+
         match arg.as_str() {
             flag if arg.starts_with('-') => match flag {
                 "-f" | "--flag" => {
                     let next = next_arg_for!(flag)?;
-                    settings.flag = lookup_pattern(next.as_str())
+                    flag_choice = Some(FlagChoice::Named(flag.to_owned(), next));
+                }
+                "--colors" => {
+                    let next = next_arg_for!(flag)?;
+                    let custom = next.split(',')
+                        .map(|c| parse_hex_color(c.trim()))
+                        .collect::<Option<Vec<_>>>()
+                        .and_then(|colors| FlagDefinition::from_stripes("custom", colors, ColorStripes::DEFAULT_FACTOR))
                         .ok_or_else(|| badval![next,flag])?;
+                    flag_choice = Some(FlagChoice::Colors(custom));
+                }
+                "--flag-file" => {
+                    let next = next_arg_for!(flag)?;
+                    flag_files.push(next);
                 }
                 "-h" | "--horizontal-frequency" => {
                     let next = next_arg_for!(flag)?;
@@ -378,7 +365,17 @@ fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Settings, ParseA
                         .map_err(|_| badval![next,flag])?;
                 }
                 "-F" | "--force-color" => {
-                    settings.enable_color = true;
+                    color_when = ColorWhen::Always;
+                }
+                "--color" => {
+                    // like ls and grep, a bare --color means always
+                    let next = inline_value.take().map(|(_, value)| value);
+                    color_when = match next.as_deref() {
+                        None | Some("always") => ColorWhen::Always,
+                        Some("auto") => ColorWhen::Auto,
+                        Some("never") => ColorWhen::Never,
+                        Some(_) => return Err(badval![next.unwrap_or_default(),flag]),
+                    };
                 }
 //                "-l" | "--no-force-locale" => {
 //                    settings.force_locale = false;
@@ -387,7 +384,66 @@ fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Settings, ParseA
                     settings.enable_rand_offset = true;
                 }
                 "-b" | "--24bit" => {
-                    settings.color_type = OutputColorType::TwentyFourBit;
+                    color_type = Some(OutputColorType::TwentyFourBit);
+                }
+                "--color-depth" => {
+                    let next = next_arg_for!(flag)?;
+                    color_type = match next.as_str() {
+                        "auto" => None,
+                        "8" => Some(OutputColorType::Basic8),
+                        "16" => Some(OutputColorType::Basic16),
+                        "256" => Some(OutputColorType::Ansii),
+                        "24bit" | "truecolor" => Some(OutputColorType::TwentyFourBit),
+                        _ => return Err(badval![next,flag]),
+                    };
+                }
+                "--preserve-colors" => {
+                    settings.preserve_colors = true;
+                }
+                "--strip-ansi" => {
+                    // the argument is optional, so only accept it as --strip-ansi=all
+                    let next = inline_value.take().map(|(_, value)| value);
+                    strip = Some(match next.as_deref() {
+                        None | Some("sgr") => StripMode::Sgr,
+                        Some("all") => StripMode::All,
+                        Some(_) => return Err(badval![next.unwrap_or_default(),flag]),
+                    });
+                }
+                "--strip-only" => {
+                    settings.strip_only = true;
+                }
+                "--tabsize" => {
+                    let next = next_arg_for!(flag)?;
+                    settings.tab_size = next.parse().ok()
+                        .filter(|size| *size > 0)
+                        .ok_or_else(|| badval![next,flag])?;
+                }
+                "--expand-tabs" => {
+                    settings.expand_tabs = true;
+                }
+                "--replace-invalid" => {
+                    settings.replace_invalid = true;
+                }
+                "-j" | "--jobs" => {
+                    let next = next_arg_for!(flag)?;
+                    settings.jobs = next.parse().ok()
+                        .filter(|jobs| *jobs > 0)
+                        .ok_or_else(|| badval![next,flag])?;
+                }
+                "--binary" => {
+                    let next = next_arg_for!(flag)?;
+                    settings.binary = match next.as_str() {
+                        "passthrough" => BinaryPolicy::Passthrough,
+                        "skip" => BinaryPolicy::Skip,
+                        "colorize" => BinaryPolicy::Colorize,
+                        _ => return Err(badval![next,flag]),
+                    };
+                }
+                "--256smooth" => {
+                    color_type = Some(OutputColorType::AnsiiSmooth);
+                }
+                "--exact-colors" => {
+                    settings.exact_colors = true;
                 }
                 "--help" => {
                     settings.print_help = true;
@@ -410,8 +466,64 @@ fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Settings, ParseA
                 settings.file_names.push(arg);
             }
         }
+
+        if let Some((opt, _)) = inline_value.take() {
+            return Err(usage!["'{opt}' option doesn't take an argument!"]);
+        }
+    }
+
+    // the library and flag files may define the flag chosen with -f, so load
+    // them all before looking it up. a broken library file shouldn't stop
+    // queercat from working, but one named on the command line should.
+    for dir in library_dirs() {
+        let files = match load_flag_dir(&dir) {
+            Ok(files) => files,
+            Err(e) => {
+                report!("warning: {}: {}", dir.display(), describe_io_error(&e));
+                continue;
+            }
+        };
+        for (path, loaded) in files {
+            match loaded {
+                Ok(loaded) => settings.add_flags(loaded, path),
+                Err(e) => report!("warning: {}: {}", path.display(), describe_flag_file_error(&e)),
+            }
+        }
+    }
+    for path in flag_files {
+        let loaded = load_flag_file(&path)
+            .map_err(|e| ParseArgsFail::BadFlagFile(path.clone(), e))?;
+        settings.add_flags(loaded, path.into());
+    }
+    match flag_choice {
+        Some(FlagChoice::Named(flag, next)) => {
+            let flags: Vec<_> = settings.known_flags.iter().map(|f| f.def).collect();
+            use ParseArgsFail::*;
+            settings.flag = lookup_pattern_in(&flags, next.as_str())
+                .ok_or_else(|| badval![next,flag])?;
+        }
+        Some(FlagChoice::Colors(custom)) => {
+            settings.flag = Box::leak(Box::new(custom));
+        }
+        None => {}
     }
 
+    settings.enable_color = match color_when {
+        ColorWhen::Always => true,
+        ColorWhen::Never => false,
+        ColorWhen::Auto => color_from_env().unwrap_or(settings.enable_color),
+    };
+
+    settings.strip = match strip {
+        Some(strip) => strip,
+        None if settings.strip_only => StripMode::All,
+        None => StripMode::Keep,
+    };
+
+    settings.color_type = color_type
+        .or_else(detect_color_type)
+        .unwrap_or(Settings::DEFAULT_COLOR_TYPE);
+
     // read stdin if no files specified
     if settings.file_names.is_empty() {
         settings.file_names.push("-".into());
@@ -422,6 +534,7 @@ fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Settings, ParseA
 
 enum QueercatFatalError {
     BadCommandLine(String),
+    BadFlagFile(String, FlagFileError),
     IoError(std::io::Error)
 }
 
@@ -431,28 +544,62 @@ impl From<std::io::Error> for QueercatFatalError {
     }
 }
 
-impl std::fmt::Debug for QueercatFatalError {
+impl QueercatFatalError {
+    /// Whoever was reading the output stopped; nothing to complain about.
+    fn is_broken_pipe(&self) -> bool {
+        matches!(self, QueercatFatalError::IoError(e) if e.kind() == io::ErrorKind::BrokenPipe)
+    }
+
+    /// 2 for bad usage, like most command line tools; 1 for anything else
+    /// going wrong; and for a closed pipe, what the shell would report for
+    /// a program killed by SIGPIPE, like cat would be.
+    fn exit_code(&self) -> ExitCode {
+        use QueercatFatalError::*;
+        match self {
+            BadCommandLine(_) => ExitCode::from(2),
+            _ if self.is_broken_pipe() => ExitCode::from(128 + 13),
+            BadFlagFile(..) | IoError(_) => ExitCode::FAILURE,
+        }
+    }
+}
+
+impl std::fmt::Display for QueercatFatalError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         use QueercatFatalError::*;
         match self {
             BadCommandLine(msg) => {
                 writeln!(f, "{}", msg)?;
-                writeln!(f, "Try 'queercat --help' for more information.")
+                write!(f, "Try 'queercat --help' for more information.")
+            }
+            BadFlagFile(path, e) => write!(f, "{path}: {}", describe_flag_file_error(e)),
+            IoError(e) => write!(f, "{}", describe_io_error(e)),
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(code) => code,
+        Err(e) => {
+            if !e.is_broken_pipe() {
+                report!("{e}");
             }
-            IoError(e) => e.fmt(f),
+            e.exit_code()
         }
     }
 }
 
-fn main() -> Result<(), QueercatFatalError> {
+fn run() -> Result<ExitCode, QueercatFatalError> {
     let settings = match parse_args(std::env::args()) {
         Ok(s) => s,
         Err(ParseArgsFail::PrintUsage(msg)) =>
             return Err(QueercatFatalError::BadCommandLine(msg)),
         Err(ParseArgsFail::PrintVersion) => {
-            print_version();
-            return Ok(());
+            print_version()?;
+            return Ok(ExitCode::SUCCESS);
         }
+        Err(ParseArgsFail::BadFlagFile(path, e)) =>
+            return Err(QueercatFatalError::BadFlagFile(path, e)),
     };
 
     //int rand_offset = 0;
@@ -465,6 +612,7 @@ fn main() -> Result<(), QueercatFatalError> {
     } else {
         0
     };
+    let colorizer = settings.colorizer(rand_offset);
 
     /* Handle locale. */ // don't *think* we actually need/care about this?
     /*
@@ -478,77 +626,139 @@ fn main() -> Result<(), QueercatFatalError> {
     }
     */
 
-    /* TODO? revisit this idea of colorizing via an iterator
-    fn colorizer(src: impl Iterator<Item = char>, settings: Settings) -> impl Iterator<Item = char>
-    {
-        let mut n = 0;
-        src.flat_map(move |c| {
-            n += 1;
-            format!["{c}{n}"] .chars().into_iter()
-        })
-    }
-    */
-
     use std::fs::File;
-    use std::io::{self, Read};
 
-    let files: Box<dyn Iterator<Item = io::Result<Box<dyn Read>>>> =
+    type NamedInput<'n> = (&'n str, io::Result<Box<dyn Read>>); // name for messages, and the input
+
+    let files: Box<dyn Iterator<Item = NamedInput>> =
         if settings.print_help
         {
-            let r: Box<dyn Read> = Box::new(io::Cursor::new(build_helpstr()));
-            Box::new(std::iter::once(Ok(r)))
+            let r: Box<dyn Read> = Box::new(io::Cursor::new(build_helpstr(&settings.known_flags)));
+            Box::new(std::iter::once(("--help", Ok(r))))
         }
         else
         {
-            let file_iterator = settings.file_names.iter().map(|filename| -> io::Result<Box<dyn Read>> {
+            let file_iterator = settings.file_names.iter().map(|filename| -> NamedInput {
                 match filename.as_str() {
-                    "-" => Ok(Box::new(io::stdin())),
-                    _ => Ok(Box::new(File::open(filename)?))
+                    "-" => ("standard input", Ok(Box::new(io::stdin().lock()))),
+                    _ => (filename, File::open(filename).map(|f| -> Box<dyn Read> { Box::new(f) })),
                 }
             });
             Box::new(file_iterator)
         };
 
-    for file in files {
-        if !settings.enable_color {
-            let mut reader = file?;
-            let _ = io::copy(&mut reader, &mut io::stdout())?;
-            continue;
+    // stdout is line buffered already, which is what's wanted when someone's
+    // watching; otherwise, fewer and bigger writes are much faster
+    let stdout = io::stdout();
+    let mut out: Box<dyn Write> = if stdout.is_terminal() {
+        Box::new(stdout.lock())
+    } else {
+        Box::new(BufWriter::with_capacity(BUFFER_SIZE, stdout.lock()))
+    };
+
+    let mut failed = false;
+    for (name, file) in files {
+        match file.map_err(CopyError::Read).and_then(|file| cat_file(name, file, &mut out, &settings, &colorizer)) {
+            Ok(()) => {}
+            Err(CopyError::Read(e)) => {
+                // like cat, complain and carry on with the next file
+                report!("{name}: {}", describe_io_error(&e));
+                failed = true;
+            }
+            Err(CopyError::Write(e)) => return Err(e.into()),
         }
+    }
 
-        use std::io::{BufRead, BufReader};
+    Ok(if failed { ExitCode::FAILURE } else { ExitCode::SUCCESS })
+}
 
-        let mut reader = BufReader::new(file?);
-        let mut line_index = 0;
-        let mut escape_state = EscapeState::Out;
+/// For reading input and buffering output.
+const BUFFER_SIZE: usize = 64 * 1024;
+/// For reading input, per thread, when colorizing on several.
+const PARALLEL_BUFFER_SIZE: usize = 256 * 1024;
 
-        let mut line: String = Default::default();
-        while let Ok(read) = reader.read_line(&mut line) {
-            if read == 0 {
-                break;
-            }
+/// Which side of a copy went wrong.
+enum CopyError {
+    Read(io::Error),
+    Write(io::Error),
+}
 
-            for (char_index, current_char) in line.chars().enumerate() {
-                let char_index = char_index as u32;
+/// Like `io::copy`, but keeping track of which side failed.
+fn copy(reader: &mut impl BufRead, writer: &mut impl Write) -> Result<(), CopyError> {
+    loop {
+        let buf = match reader.fill_buf() {
+            Ok([]) => return Ok(()),
+            Ok(buf) => buf,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(CopyError::Read(e)),
+        };
+        let len = buf.len();
+        writer.write_all(buf).map_err(CopyError::Write)?;
+        reader.consume(len);
+    }
+}
 
-                find_escape_sequences(current_char, &mut escape_state);
+/// Copy one input file to `out`, colorizing it as `settings` say.
+///
+/// `out` is flushed at the end, so that any messages about the next file
+/// come after this one's output.
+fn cat_file(
+    name: &str,
+    file: Box<dyn Read>,
+    mut out: &mut dyn Write,
+    settings: &Settings,
+    colorizer: &Colorizer,
+) -> Result<(), CopyError> {
+    // read enough at once to give every thread something to do
+    let capacity = if settings.jobs > 1 { settings.jobs * PARALLEL_BUFFER_SIZE } else { BUFFER_SIZE };
+    let mut reader = BufReader::with_capacity(capacity, file);
+
+    let binary = match settings.binary {
+        BinaryPolicy::Colorize => false,
+        BinaryPolicy::Passthrough if settings.passthrough() => false, // copied as is anyway
+        _ => looks_binary(reader.fill_buf().map_err(CopyError::Read)?),
+    };
+    if binary && settings.binary == BinaryPolicy::Skip {
+        report!("warning: {name}: looks like a binary file; skipped");
+        return Ok(());
+    }
 
-                if escape_state == EscapeState::Out {
-                    print_color(&settings, char_index, line_index, rand_offset);
-                }
+    if settings.passthrough() || binary {
+        let copied = copy(&mut reader, &mut out);
+        out.flush().map_err(CopyError::Write)?;
+        return copied;
+    }
 
-                print!("{current_char}");
+    let mut writer = QueercatWriter::new(out, colorizer.clone())
+        .with_replace_invalid(settings.replace_invalid)
+        .with_threads(settings.jobs);
+    let copied = copy(&mut reader, &mut writer);
+
+    // even if reading failed part way, don't leave the terminal colored
+    let invalid = writer.invalid_bytes();
+    writer.finish().map_err(CopyError::Write)?;
+    if invalid > 0 {
+        let action = if settings.replace_invalid { "replaced" } else { "passed through" };
+        let bytes = if invalid == 1 { "byte" } else { "bytes" };
+        report!("warning: {name}: {invalid} {bytes} of invalid UTF-8 {action}");
+    }
 
-                if escape_state == EscapeState::Last {
-                    print_color(&settings, char_index, line_index, rand_offset);
-                }
-            }
+    copied
+}
 
-            line_index += 1;
-            line.clear();
-        }
-        print!("{}[0m", ESCAPE_CHAR);
+/// An I/O error's message, without the "(os error N)" Rust adds to it.
+fn describe_io_error(e: &io::Error) -> String {
+    let message = e.to_string();
+    match message.rfind(" (os error ") {
+        Some(end) => message[..end].to_owned(),
+        None => message,
     }
+}
 
-    Ok(())
+/// A flag file error's message, with I/O errors described like any other.
+fn describe_flag_file_error(e: &FlagFileError) -> String {
+    match e {
+        FlagFileError::Io(e) => describe_io_error(e),
+        FlagFileError::Invalid { .. } => e.to_string(),
+    }
 }
@@ -0,0 +1,213 @@
+//! Colorizing big inputs on several threads at once.
+//!
+//! A line's colors only depend on its own text, its line number, and the
+//! [`LineState`] left by the lines before it. Working out that state is
+//! much cheaper than colorizing, so the input is split into chunks of whole
+//! lines, a quick pass finds the state each chunk starts in, and then the
+//! chunks are colorized side by side.
+
+use std::thread;
+
+use crate::escape::EscapeState;
+use crate::{Colorizer, ColorizerState, LineState, StripMode};
+
+/// Chunks smaller than this aren't worth a thread of their own.
+const MIN_CHUNK_LEN: usize = 64 * 1024;
+
+impl Colorizer<'_> {
+    /// Like [`colorize_into`](Colorizer::colorize_into), but using up to
+    /// `threads` threads for big inputs.
+    ///
+    /// Each chunk starts by setting its color, so the output can have a few
+    /// more escapes than colorizing it all in one go would, but otherwise
+    /// looks just the same.
+    pub fn colorize_parallel_into(&self, state: &mut ColorizerState, input: &str, threads: usize, out: &mut String) {
+        // anything after the last newline has to wait for the next line's state
+        let whole_lines = input.rfind('\n').map_or(0, |end| end + 1);
+        let threads = threads.min(whole_lines / MIN_CHUNK_LEN);
+        if threads <= 1 {
+            return self.colorize_into(state, input, out);
+        }
+
+        let (lines, rest) = input.split_at(whole_lines);
+        let pieces = split_lines(lines, threads);
+
+        // the first chunk carries on from wherever `state` is; the others
+        // start at the beginning of a line, in whatever state the chunk
+        // before left things. A newline that gets stripped out along with
+        // an escape sequence doesn't end the line, so a piece ending in one
+        // is joined onto the next.
+        let mut chunks = Vec::with_capacity(pieces.len());
+        let mut starts = Vec::with_capacity(pieces.len());
+        let mut line = state.line.clone();
+        let (mut chunk_start, mut end) = (0, 0);
+        for piece in &pieces[..pieces.len() - 1] {
+            end += piece.len();
+            if self.skip_lines(&mut line, piece) {
+                chunks.push(&lines[chunk_start..end]);
+                starts.push(ColorizerState::at_line_start(line.clone()));
+                chunk_start = end;
+            }
+        }
+        chunks.push(&lines[chunk_start..]);
+
+        let finished = thread::scope(|scope| {
+            let workers = chunks[1..].iter().zip(starts)
+                .map(|(chunk, mut state)| scope.spawn(move || {
+                    let mut out = String::new();
+                    self.colorize_into(&mut state, chunk, &mut out);
+                    (state, out)
+                }))
+                .collect::<Vec<_>>();
+
+            self.colorize_into(state, chunks[0], out);
+            workers.into_iter()
+                .map(|worker| worker.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic)))
+                .collect::<Vec<_>>()
+        });
+
+        for (chunk_state, chunk_out) in finished {
+            out.push_str(&chunk_out);
+            *state = chunk_state;
+        }
+        self.colorize_into(state, rest, out);
+    }
+
+    /// Move `line` past `lines`, which ends with a newline, without colorizing them.
+    ///
+    /// Returns whether that newline made it past the stripper, so that what
+    /// comes after starts a new line.
+    fn skip_lines(&self, line: &mut LineState, lines: &str) -> bool {
+        // ESC, or a C1 control encoded as UTF-8
+        let could_start_sequence = |bytes: &[u8]| bytes.contains(&0x1b)
+            || bytes.windows(2).any(|pair| pair[0] == 0xc2 && (0x80..=0x9f).contains(&pair[1]));
+
+        if line.escape_state == EscapeState::Ground && line.stripper.is_idle() && !could_start_sequence(lines.as_bytes()) {
+            // nothing but plain text, which leaves everything but the line number alone
+            line.line_index += lines.bytes().filter(|b| *b == b'\n').count() as u32;
+            return true;
+        }
+
+        let mut stripped = String::new();
+        let mut ends_line = false;
+        for c in lines.chars() {
+            if self.strip == StripMode::Keep {
+                line.advance(c);
+                ends_line = c == '\n';
+            } else {
+                line.stripper.feed(self.strip, c, &mut stripped);
+                for c in stripped.drain(..) {
+                    line.advance(c);
+                    ends_line = c == '\n';
+                }
+            }
+        }
+        ends_line
+    }
+}
+
+/// Split `lines` into about `count` pieces, each ending at the end of a line.
+fn split_lines(lines: &str, count: usize) -> Vec<&str> {
+    let target = lines.len() / count;
+    let mut chunks = Vec::with_capacity(count);
+    let mut rest = lines;
+    while !rest.is_empty() {
+        // a newline byte can't be part of a longer UTF-8 sequence, so this
+        // always splits between characters
+        let end = match rest.as_bytes().get(target..).and_then(|after| after.iter().position(|b| *b == b'\n')) {
+            Some(newline) if chunks.len() + 1 < count => target + newline + 1,
+            _ => rest.len(),
+        };
+        let (chunk, after) = rest.split_at(end);
+        chunks.push(chunk);
+        rest = after;
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::escape::CharKind;
+    use crate::{lookup_pattern, OutputColorType};
+
+    fn colorizer(color_type: OutputColorType) -> Colorizer<'static> {
+        let flag = lookup_pattern("transgender").expect("built-in flag");
+        Colorizer::new(flag, 0.23, 0.1, 0.0, color_type)
+    }
+
+    /// `input` repeated until there's enough of it to be split across a few threads.
+    fn big(input: &str) -> String {
+        input.repeat(3 * MIN_CHUNK_LEN / input.len() + 1)
+    }
+
+    /// `output` without any escape sequence that's the same as the one before
+    /// it, i.e. the extra color changes at the start of each chunk.
+    fn without_repeats(output: &str) -> String {
+        let mut state = EscapeState::Ground;
+        let mut result = String::new();
+        let mut sequence = String::new();
+        let mut last_sequence = String::new();
+        for c in output.chars() {
+            match state.advance(c) {
+                CharKind::InSequence => sequence.push(c),
+                CharKind::SequenceEnd => {
+                    sequence.push(c);
+                    if sequence != last_sequence {
+                        result.push_str(&sequence);
+                        last_sequence = sequence.clone();
+                    }
+                    sequence.clear();
+                }
+                _ => result.push(c),
+            }
+        }
+        result + &sequence
+    }
+
+    fn assert_same_as_serial(colorizer: &Colorizer, input: &str) {
+        let mut state = ColorizerState::default();
+        let mut serial = String::new();
+        colorizer.colorize_into(&mut state, input, &mut serial);
+        colorizer.finish_into(&mut state, &mut serial);
+
+        for threads in [2, 3, 8] {
+            let mut state = ColorizerState::default();
+            let mut parallel = String::new();
+            colorizer.colorize_parallel_into(&mut state, input, threads, &mut parallel);
+            colorizer.finish_into(&mut state, &mut parallel);
+            assert!(without_repeats(&parallel) == without_repeats(&serial), "differs with {threads} threads");
+        }
+    }
+
+    #[test]
+    fn plain_text() {
+        let input = big("the quick brown fox\njumps over\tthe lazy dog \u{1f3f3}\u{fe0f}\u{200d}\u{26a7}\u{fe0f}\n");
+        assert_same_as_serial(&colorizer(OutputColorType::TwentyFourBit), &input);
+        assert_same_as_serial(&colorizer(OutputColorType::Ansii), &(input + "no newline at the end"));
+    }
+
+    #[test]
+    fn sgr_and_unterminated_csi() {
+        // every line ends in the middle of a control sequence, so wherever
+        // the chunks split, the next one starts inside it
+        let input = big("plain \x1b[31mred\x1b[0m plain \x1b]8;;https://example.com\x07link\x1b]8;;\x07 \x1b[1;\n32mgreen \x1b[0m\n");
+
+        let colorizer = colorizer(OutputColorType::TwentyFourBit);
+        assert_same_as_serial(&colorizer, &input);
+        assert_same_as_serial(&colorizer.clone().with_preserve_colors(true), &input);
+        assert_same_as_serial(&colorizer.clone().with_strip(StripMode::Sgr), &input);
+        assert_same_as_serial(&colorizer.clone().with_strip(StripMode::All), &input);
+    }
+
+    #[test]
+    fn chunks_end_at_newlines() {
+        let lines = "a\nbb\nccc\ndddd\n".repeat(100);
+        for count in 1..10 {
+            let chunks = split_lines(&lines, count);
+            assert!(chunks.len() <= count);
+            assert_eq!(chunks.concat(), lines);
+            assert!(chunks.iter().all(|chunk| chunk.ends_with('\n')));
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use std::collections::VecDeque;
+use std::iter::Peekable;
+
+use crate::{Color, Colorizer, ColorizerState, Step, StripMode};
+
+/// Iterator returned by [`Colorizer::colored_chars`].
+pub struct ColoredChars<'c, I: Iterator<Item = char>> {
+    colorizer: &'c Colorizer<'c>,
+    chars: I,
+    state: ColorizerState,
+    stripped: VecDeque<char>, // made it through the stripper, but not yet returned
+    spaces: u32, // left to return from an expanded tab
+}
+
+impl<'c, I: Iterator<Item = char>> ColoredChars<'c, I> {
+    pub(crate) fn new(colorizer: &'c Colorizer<'c>, chars: I) -> Self {
+        ColoredChars {
+            colorizer,
+            chars,
+            state: ColorizerState::default(),
+            stripped: VecDeque::new(),
+            spaces: 0,
+        }
+    }
+
+    fn next_char(&mut self) -> Option<char> {
+        let strip = self.colorizer.strip;
+        if strip == StripMode::Keep {
+            return self.chars.next();
+        }
+
+        loop {
+            if let Some(c) = self.stripped.pop_front() {
+                return Some(c);
+            }
+
+            let mut out = std::mem::take(&mut self.state.stripped);
+            match self.chars.next() {
+                Some(c) => self.state.line.stripper.feed(strip, c, &mut out),
+                None => {
+                    self.state.line.stripper.flush(&mut out);
+                    if out.is_empty() {
+                        return None;
+                    }
+                }
+            }
+            self.stripped.extend(out.drain(..));
+            self.state.stripped = out;
+        }
+    }
+}
+
+impl<I: Iterator<Item = char>> Iterator for ColoredChars<'_, I> {
+    type Item = (char, Option<Color>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.spaces > 0 {
+            self.spaces -= 1;
+            return Some((' ', None));
+        }
+
+        let c = self.next_char()?;
+        let color = match self.colorizer.step(&mut self.state, c) {
+            Step::Text(color) | Step::Joined(color) => Some(color),
+            Step::Plain => None,
+            Step::Spaces(count) => {
+                self.spaces = count - 1;
+                return Some((' ', None));
+            }
+        };
+        Some((c, color))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (_, upper) = self.chars.size_hint();
+        let ready = self.stripped.len() + self.spaces as usize;
+        // an expanded tab can turn into any number of spaces
+        let upper = if self.colorizer.expand_tabs { None } else { upper.map(|n| n + ready + self.state.line.stripper.held_len()) };
+        (ready, upper)
+    }
+}
+
+/// A run of text that all gets the same color.
+///
+/// `color` is `None` for control characters (and spaces expanded from tabs),
+/// escape sequences that were already in the input, and (with
+/// `preserve_colors`) text the input colored.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Span {
+    pub text: String,
+    pub color: Option<Color>,
+}
+
+/// Iterator returned by [`Colorizer::spans`].
+pub struct Spans<'c, I: Iterator<Item = char>> {
+    chars: Peekable<ColoredChars<'c, I>>,
+}
+
+impl<'c, I: Iterator<Item = char>> Spans<'c, I> {
+    pub(crate) fn new(chars: ColoredChars<'c, I>) -> Self {
+        Spans { chars: chars.peekable() }
+    }
+}
+
+impl<I: Iterator<Item = char>> Iterator for Spans<'_, I> {
+    type Item = Span;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (c, color) = self.chars.next()?;
+        let mut text = String::from(c);
+
+        while let Some((c, _)) = self.chars.next_if(|(_, next)| *next == color) {
+            text.push(c);
+        }
+
+        Some(Span { text, color })
+    }
+}
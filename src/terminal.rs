@@ -0,0 +1,377 @@
+//! Working out how many colors the terminal can show.
+
+use std::env::var_os;
+use std::ffi::OsStr;
+use std::path::PathBuf;
+
+use crate::OutputColorType;
+
+/// Guess the best color type for the terminal described by the environment.
+///
+/// In order of precedence, this looks at:
+///  1. `COLORTERM`, which truecolor terminals set to `truecolor` or `24bit`;
+///  2. the terminfo entry for `TERM`: its `Tc` or `RGB` flags, then its
+///     `colors` count;
+///  3. conventional `TERM` suffixes like `-256color`, for systems without
+///     the terminfo entry installed.
+///
+/// A terminfo entry with only 8 or 16 colors is only believed when `TERM`'s
+/// name asks for that few too (like `xterm-16color` or `linux`): plenty of
+/// terminals that do 256 colors still set plain `TERM=xterm`.
+///
+/// Returns `None` if none of those say anything useful.
+pub fn detect_color_type() -> Option<OutputColorType> {
+    let colorterm = var_os("COLORTERM");
+    if colorterm.as_deref().is_some_and(|c| c == "truecolor" || c == "24bit") {
+        return Some(OutputColorType::TwentyFourBit);
+    }
+
+    let term = var_os("TERM").filter(|t| !t.is_empty())?;
+    let term = term.to_str()?;
+
+    color_type_for(term, Terminfo::load(term))
+}
+
+/// The color type for `TERM=term`, given its terminfo entry if there is one.
+fn color_type_for(term: &str, info: Option<Terminfo>) -> Option<OutputColorType> {
+    use OutputColorType::*;
+
+    let from_name = color_type_from_name(term);
+    match info.and_then(|info| info.color_type()) {
+        Some(Basic8 | Basic16) if !matches!(from_name, Some(Basic8 | Basic16)) => Some(Ansii),
+        Some(color_type) => Some(color_type),
+        None => from_name,
+    }
+}
+
+fn color_type_from_name(term: &str) -> Option<OutputColorType> {
+    if term.ends_with("-truecolor") || term.ends_with("-direct") || term.ends_with("-24bit") {
+        Some(OutputColorType::TwentyFourBit)
+    } else if term.ends_with("-256color") {
+        Some(OutputColorType::Ansii)
+    } else if term.ends_with("-16color") {
+        Some(OutputColorType::Basic16)
+    } else if term.ends_with("-color") || term.ends_with("-8color") || term == "linux" {
+        Some(OutputColorType::Basic8)
+    } else {
+        None
+    }
+}
+
+fn color_type_from_count(colors: i32) -> Option<OutputColorType> {
+    match colors {
+        0x1000000.. => Some(OutputColorType::TwentyFourBit),
+        256.. => Some(OutputColorType::Ansii),
+        16.. => Some(OutputColorType::Basic16),
+        8.. => Some(OutputColorType::Basic8),
+        _ => None,
+    }
+}
+
+/// The few capabilities we care about from a compiled terminfo entry.
+///
+/// See term(5) for the file format.
+#[derive(Default)]
+struct Terminfo {
+    colors: Option<i32>,
+    truecolor: bool, // the Tc or RGB extended capability
+}
+
+impl Terminfo {
+    const MAGIC_16BIT: u16 = 0o432;
+    const MAGIC_32BIT: u16 = 0o1036;
+    const COLORS_INDEX: usize = 13; // position of "colors" among the numeric capabilities
+
+    fn color_type(&self) -> Option<OutputColorType> {
+        if self.truecolor {
+            return Some(OutputColorType::TwentyFourBit);
+        }
+        color_type_from_count(self.colors?)
+    }
+
+    fn load(term: &str) -> Option<Terminfo> {
+        let first = term.chars().next()?;
+        Self::search_dirs().into_iter()
+            .flat_map(|dir| [
+                dir.join(first.to_string()).join(term),
+                dir.join(format!["{:x}", first as u32]).join(term), // macOS layout
+            ])
+            .find_map(|path| std::fs::read(path).ok())
+            .and_then(|data| Self::parse(&data))
+    }
+
+    fn search_dirs() -> Vec<PathBuf> {
+        let mut dirs = Vec::new();
+        if let Some(dir) = var_os("TERMINFO") {
+            dirs.push(PathBuf::from(dir));
+        }
+        if let Some(home) = var_os("HOME") {
+            dirs.push(PathBuf::from(home).join(".terminfo"));
+        }
+        if let Some(list) = var_os("TERMINFO_DIRS") {
+            // an empty entry means "the system default"
+            dirs.extend(std::env::split_paths(&list).filter(|d| d != OsStr::new("")));
+        }
+        dirs.extend(["/etc/terminfo", "/lib/terminfo", "/usr/share/terminfo", "/usr/lib/terminfo"].map(PathBuf::from));
+        dirs
+    }
+
+    fn parse(data: &[u8]) -> Option<Terminfo> {
+        let mut reader = Reader { data, pos: 0 };
+
+        let magic = reader.u16()?;
+        let num_size = match magic {
+            Self::MAGIC_16BIT => 2,
+            Self::MAGIC_32BIT => 4,
+            _ => return None,
+        };
+        let names_size = reader.count()?;
+        let bool_count = reader.count()?;
+        let num_count = reader.count()?;
+        let str_count = reader.count()?;
+        let str_table_size = reader.count()?;
+
+        reader.skip(names_size + bool_count)?;
+        reader.align();
+
+        let mut info = Terminfo::default();
+        for i in 0..num_count {
+            let n = reader.number(num_size)?;
+            if i == Self::COLORS_INDEX && n >= 0 {
+                info.colors = Some(n);
+            }
+        }
+
+        reader.skip(str_count * 2 + str_table_size)?;
+        reader.align();
+
+        // the extended section is optional; ignore it if it's missing or mangled
+        if let Some(truecolor) = Self::parse_extended(&mut reader, num_size) {
+            info.truecolor = truecolor;
+        }
+
+        Some(info)
+    }
+
+    /// Whether the extended capabilities include `Tc` or `RGB`.
+    fn parse_extended(reader: &mut Reader, num_size: usize) -> Option<bool> {
+        let bool_count = reader.count()?;
+        let num_count = reader.count()?;
+        let str_count = reader.count()?;
+        let _offset_count = reader.count()?;
+        let _table_size = reader.count()?;
+
+        let bools = reader.take(bool_count)?;
+        reader.align();
+        let nums = (0..num_count).map(|_| reader.number(num_size)).collect::<Option<Vec<_>>>()?;
+        let str_offsets = (0..str_count).map(|_| reader.u16()).collect::<Option<Vec<_>>>()?;
+        let name_offsets = (0..bool_count + num_count + str_count).map(|_| reader.u16()).collect::<Option<Vec<_>>>()?;
+        let table = &reader.data[reader.pos..];
+
+        let string_at = |offset: usize| -> Option<&[u8]> {
+            let rest = table.get(offset..)?;
+            let len = rest.iter().position(|b| *b == 0)?;
+            Some(&rest[..len])
+        };
+
+        // names come after the string values in the table
+        let mut names_start = 0;
+        for offset in str_offsets.into_iter().filter(|o| *o != 0xffff && *o != 0xfffe) {
+            names_start = names_start.max(offset as usize + string_at(offset as usize)?.len() + 1);
+        }
+        let name = |i: usize| string_at(names_start + name_offsets[i] as usize);
+
+        let is_truecolor = |n: &[u8]| n == b"Tc" || n == b"RGB";
+        for (i, set) in bools.iter().enumerate() {
+            if *set == 1 && is_truecolor(name(i)?) {
+                return Some(true);
+            }
+        }
+        for (i, n) in nums.iter().enumerate() {
+            if *n > 0 && is_truecolor(name(bool_count + i)?) {
+                return Some(true);
+            }
+        }
+        Some(false)
+    }
+}
+
+struct Reader<'d> {
+    data: &'d [u8],
+    pos: usize,
+}
+
+impl<'d> Reader<'d> {
+    fn take(&mut self, len: usize) -> Option<&'d [u8]> {
+        let bytes = self.data.get(self.pos..self.pos + len)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    fn skip(&mut self, len: usize) -> Option<()> {
+        self.take(len).map(|_| ())
+    }
+
+    /// Sections start on even offsets.
+    fn align(&mut self) {
+        self.pos += self.pos % 2;
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        let bytes = self.take(2)?;
+        Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    /// A section size from a header. Negative means "none".
+    fn count(&mut self) -> Option<usize> {
+        let n = self.u16()? as i16;
+        Some(n.max(0) as usize)
+    }
+
+    /// A numeric capability. Negative means absent or cancelled.
+    fn number(&mut self, size: usize) -> Option<i32> {
+        let bytes = self.take(size)?;
+        Some(match bytes {
+            [a, b] => i16::from_le_bytes([*a, *b]) as i32,
+            [a, b, c, d] => i32::from_le_bytes([*a, *b, *c, *d]),
+            _ => unreachable!("numbers are 2 or 4 bytes"),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A compiled terminfo entry with `colors` (if given) and the extended
+    /// boolean capabilities `flags`, laid out like tic(1) writes them.
+    fn entry(magic: u16, colors: Option<i32>, flags: &[&str]) -> Vec<u8> {
+        let num_size = if magic == Terminfo::MAGIC_32BIT { 4 } else { 2 };
+        let mut data = Vec::new();
+        let u16 = |data: &mut Vec<u8>, n: usize| data.extend((n as u16).to_le_bytes());
+        let number = |data: &mut Vec<u8>, n: i32| match num_size {
+            2 => data.extend((n as i16).to_le_bytes()),
+            _ => data.extend(n.to_le_bytes()),
+        };
+        let align = |data: &mut Vec<u8>| if data.len() % 2 == 1 { data.push(0) };
+
+        let names = b"test|a made-up terminal\0";
+        let bools = [1, 0, 1]; // an odd count, to need padding
+        let strings = b"\x1b[H\0";
+        let num_count = Terminfo::COLORS_INDEX + 2;
+
+        u16(&mut data, magic as usize);
+        for n in [names.len(), bools.len(), num_count, 1, strings.len()] {
+            u16(&mut data, n);
+        }
+        data.extend(names);
+        data.extend(bools);
+        align(&mut data);
+        for i in 0..num_count {
+            let n = if i == Terminfo::COLORS_INDEX { colors.unwrap_or(-1) } else { -1 };
+            number(&mut data, n);
+        }
+        u16(&mut data, 0);
+        data.extend(strings);
+        align(&mut data);
+
+        // the extended section: just booleans, and a string so the names
+        // don't start at the beginning of the table
+        let ext_names: Vec<&str> = flags.iter().copied().chain(["XT", "Ss"]).collect();
+        let ext_string = b"\x1b[%p1%d q\0";
+        let name_table: Vec<u8> = ext_names.iter().flat_map(|n| n.bytes().chain([0])).collect();
+        u16(&mut data, ext_names.len() - 1);
+        u16(&mut data, 0);
+        u16(&mut data, 1);
+        u16(&mut data, ext_names.len() + 1);
+        u16(&mut data, ext_string.len() + name_table.len());
+        data.extend(std::iter::repeat_n(1, ext_names.len() - 1));
+        align(&mut data);
+        u16(&mut data, 0);
+        let mut offset = 0;
+        for name in &ext_names {
+            u16(&mut data, offset);
+            offset += name.len() + 1;
+        }
+        data.extend(ext_string);
+        data.extend(name_table);
+        data
+    }
+
+    fn parse(data: &[u8]) -> Terminfo {
+        Terminfo::parse(data).expect("should parse")
+    }
+
+    #[test]
+    fn colors() {
+        for magic in [Terminfo::MAGIC_16BIT, Terminfo::MAGIC_32BIT] {
+            let info = parse(&entry(magic, Some(256), &[]));
+            assert_eq!(info.colors, Some(256));
+            assert!(!info.truecolor);
+            assert_eq!(info.color_type(), Some(OutputColorType::Ansii));
+        }
+        assert_eq!(parse(&entry(Terminfo::MAGIC_32BIT, Some(0x1000000), &[])).color_type(), Some(OutputColorType::TwentyFourBit));
+        assert_eq!(parse(&entry(Terminfo::MAGIC_16BIT, None, &[])).color_type(), None);
+    }
+
+    #[test]
+    fn truecolor_flags() {
+        for flag in ["Tc", "RGB"] {
+            let info = parse(&entry(Terminfo::MAGIC_16BIT, Some(256), &[flag]));
+            assert!(info.truecolor, "{flag}");
+            assert_eq!(info.color_type(), Some(OutputColorType::TwentyFourBit));
+        }
+        assert!(!parse(&entry(Terminfo::MAGIC_16BIT, Some(256), &["AX"])).truecolor);
+    }
+
+    #[test]
+    fn without_extended_section() {
+        // chop the entry off just after the legacy sections
+        let mut data = entry(Terminfo::MAGIC_16BIT, Some(8), &["Tc"]);
+        let header = |i: usize| u16::from_le_bytes([data[i * 2], data[i * 2 + 1]]) as usize;
+        let mut end = 12 + header(1) + header(2);
+        end += end % 2;
+        end += header(3) * 2 + header(4) * 2 + header(5);
+        end += end % 2;
+        data.truncate(end);
+
+        let info = parse(&data);
+        assert_eq!(info.colors, Some(8));
+        assert!(!info.truecolor);
+    }
+
+    #[test]
+    fn not_terminfo() {
+        assert!(Terminfo::parse(b"").is_none());
+        assert!(Terminfo::parse(b"#!/bin/sh\necho hi\n").is_none());
+        // cut off in the middle of the numbers
+        assert!(Terminfo::parse(&entry(Terminfo::MAGIC_16BIT, Some(8), &[])[..40]).is_none());
+    }
+
+    #[test]
+    fn names() {
+        use OutputColorType::*;
+        assert_eq!(color_type_from_name("xterm-256color"), Some(Ansii));
+        assert_eq!(color_type_from_name("foot-direct"), Some(TwentyFourBit));
+        assert_eq!(color_type_from_name("xterm-16color"), Some(Basic16));
+        assert_eq!(color_type_from_name("linux"), Some(Basic8));
+        assert_eq!(color_type_from_name("xterm"), None);
+    }
+
+    #[test]
+    fn few_colors_need_asking_for_by_name() {
+        use OutputColorType::*;
+        let info = |colors| Terminfo::parse(&entry(Terminfo::MAGIC_16BIT, Some(colors), &[]));
+
+        // plain xterm's entry says 8, but the terminal can nearly always do more
+        assert_eq!(color_type_for("xterm", info(8)), Some(Ansii));
+        assert_eq!(color_type_for("xterm-8color", info(8)), Some(Basic8));
+        assert_eq!(color_type_for("xterm-16color", info(16)), Some(Basic16));
+        assert_eq!(color_type_for("linux", info(8)), Some(Basic8));
+        assert_eq!(color_type_for("xterm-256color", info(256)), Some(Ansii));
+
+        // without an entry, the name is all there is
+        assert_eq!(color_type_for("xterm-16color", None), Some(Basic16));
+        assert_eq!(color_type_for("xterm", None), None);
+    }
+}
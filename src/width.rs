@@ -0,0 +1,410 @@
+//! How text lines up on screen: grapheme clusters and terminal cell widths.
+//!
+//! This is a pared-down take on UAX #29 (grapheme clusters) and UAX #11
+//! (East Asian Width). It covers what commonly shows up in terminals
+//! (combining accents, emoji with modifiers and ZWJ sequences, flags, and
+//! CJK text) without pulling in the full Unicode tables.
+
+const ZWJ: char = '\u{200d}';
+const VS16: char = '\u{fe0f}'; // emoji presentation selector
+
+/// Characters that attach to the one before them instead of starting a new
+/// cluster: combining marks, variation selectors, joiners, emoji modifiers
+/// and tags.
+const EXTEND: &[(char, char)] = &[
+    ('\u{0300}', '\u{036f}'),
+    ('\u{0483}', '\u{0489}'),
+    ('\u{0591}', '\u{05bd}'),
+    ('\u{05bf}', '\u{05bf}'),
+    ('\u{05c1}', '\u{05c2}'),
+    ('\u{05c4}', '\u{05c5}'),
+    ('\u{05c7}', '\u{05c7}'),
+    ('\u{0610}', '\u{061a}'),
+    ('\u{064b}', '\u{065f}'),
+    ('\u{0670}', '\u{0670}'),
+    ('\u{06d6}', '\u{06dc}'),
+    ('\u{06df}', '\u{06e4}'),
+    ('\u{06e7}', '\u{06e8}'),
+    ('\u{06ea}', '\u{06ed}'),
+    ('\u{0711}', '\u{0711}'),
+    ('\u{0730}', '\u{074a}'),
+    ('\u{07a6}', '\u{07b0}'),
+    ('\u{07eb}', '\u{07f3}'),
+    ('\u{0816}', '\u{0819}'),
+    ('\u{081b}', '\u{0823}'),
+    ('\u{0825}', '\u{0827}'),
+    ('\u{0829}', '\u{082d}'),
+    ('\u{0859}', '\u{085b}'),
+    ('\u{08d3}', '\u{08e1}'),
+    ('\u{08e3}', '\u{0903}'),
+    ('\u{093a}', '\u{093c}'),
+    ('\u{093e}', '\u{094f}'),
+    ('\u{0951}', '\u{0957}'),
+    ('\u{0962}', '\u{0963}'),
+    ('\u{0981}', '\u{0983}'),
+    ('\u{09bc}', '\u{09bc}'),
+    ('\u{09be}', '\u{09cd}'),
+    ('\u{09d7}', '\u{09d7}'),
+    ('\u{09e2}', '\u{09e3}'),
+    ('\u{0a01}', '\u{0a03}'),
+    ('\u{0a3c}', '\u{0a51}'),
+    ('\u{0a70}', '\u{0a71}'),
+    ('\u{0a75}', '\u{0a75}'),
+    ('\u{0a81}', '\u{0a83}'),
+    ('\u{0abc}', '\u{0abc}'),
+    ('\u{0abe}', '\u{0acd}'),
+    ('\u{0ae2}', '\u{0ae3}'),
+    ('\u{0b01}', '\u{0b03}'),
+    ('\u{0b3c}', '\u{0b3c}'),
+    ('\u{0b3e}', '\u{0b57}'),
+    ('\u{0b82}', '\u{0b82}'),
+    ('\u{0bbe}', '\u{0bcd}'),
+    ('\u{0bd7}', '\u{0bd7}'),
+    ('\u{0c00}', '\u{0c04}'),
+    ('\u{0c3e}', '\u{0c56}'),
+    ('\u{0c81}', '\u{0c83}'),
+    ('\u{0cbc}', '\u{0cbc}'),
+    ('\u{0cbe}', '\u{0cd6}'),
+    ('\u{0d00}', '\u{0d03}'),
+    ('\u{0d3b}', '\u{0d3c}'),
+    ('\u{0d3e}', '\u{0d4d}'),
+    ('\u{0d57}', '\u{0d57}'),
+    ('\u{0d81}', '\u{0d83}'),
+    ('\u{0dca}', '\u{0ddf}'),
+    ('\u{0df2}', '\u{0df3}'),
+    ('\u{0e31}', '\u{0e31}'),
+    ('\u{0e34}', '\u{0e3a}'),
+    ('\u{0e47}', '\u{0e4e}'),
+    ('\u{0eb1}', '\u{0eb1}'),
+    ('\u{0eb4}', '\u{0ebc}'),
+    ('\u{0ec8}', '\u{0ecd}'),
+    ('\u{0f18}', '\u{0f19}'),
+    ('\u{0f35}', '\u{0f35}'),
+    ('\u{0f37}', '\u{0f37}'),
+    ('\u{0f39}', '\u{0f39}'),
+    ('\u{0f71}', '\u{0f84}'),
+    ('\u{0f86}', '\u{0f87}'),
+    ('\u{0f8d}', '\u{0fbc}'),
+    ('\u{102b}', '\u{103e}'),
+    ('\u{1160}', '\u{11ff}'), // Hangul medial vowels and final consonants
+    ('\u{135d}', '\u{135f}'),
+    ('\u{1712}', '\u{1714}'),
+    ('\u{17b4}', '\u{17d3}'),
+    ('\u{180b}', '\u{180d}'),
+    ('\u{1ab0}', '\u{1aff}'),
+    ('\u{1b00}', '\u{1b04}'),
+    ('\u{1b34}', '\u{1b44}'),
+    ('\u{1dc0}', '\u{1dff}'),
+    ('\u{200c}', '\u{200d}'),
+    ('\u{20d0}', '\u{20ff}'),
+    ('\u{2cef}', '\u{2cf1}'),
+    ('\u{2de0}', '\u{2dff}'),
+    ('\u{302a}', '\u{302f}'),
+    ('\u{3099}', '\u{309a}'),
+    ('\u{a66f}', '\u{a672}'),
+    ('\u{a674}', '\u{a67d}'),
+    ('\u{a69e}', '\u{a69f}'),
+    ('\u{a6f0}', '\u{a6f1}'),
+    ('\u{a802}', '\u{a802}'),
+    ('\u{a806}', '\u{a806}'),
+    ('\u{a80b}', '\u{a80b}'),
+    ('\u{a823}', '\u{a827}'),
+    ('\u{a8e0}', '\u{a8f1}'),
+    ('\u{fb1e}', '\u{fb1e}'),
+    ('\u{fe00}', '\u{fe0f}'),
+    ('\u{fe20}', '\u{fe2f}'),
+    ('\u{ff9e}', '\u{ff9f}'),
+    ('\u{1f3fb}', '\u{1f3ff}'), // skin tone modifiers
+    ('\u{e0020}', '\u{e007f}'), // tags, as in subdivision flags
+    ('\u{e0100}', '\u{e01ef}'),
+];
+
+/// Characters that take up two cells: East Asian Wide and Fullwidth, and
+/// emoji shown in emoji presentation by default.
+const WIDE: &[(char, char)] = &[
+    ('\u{1100}', '\u{115f}'),
+    ('\u{231a}', '\u{231b}'),
+    ('\u{2329}', '\u{232a}'),
+    ('\u{23e9}', '\u{23ec}'),
+    ('\u{23f0}', '\u{23f0}'),
+    ('\u{23f3}', '\u{23f3}'),
+    ('\u{25fd}', '\u{25fe}'),
+    ('\u{2614}', '\u{2615}'),
+    ('\u{2648}', '\u{2653}'),
+    ('\u{267f}', '\u{267f}'),
+    ('\u{2693}', '\u{2693}'),
+    ('\u{26a1}', '\u{26a1}'),
+    ('\u{26aa}', '\u{26ab}'),
+    ('\u{26bd}', '\u{26be}'),
+    ('\u{26c4}', '\u{26c5}'),
+    ('\u{26ce}', '\u{26ce}'),
+    ('\u{26d4}', '\u{26d4}'),
+    ('\u{26ea}', '\u{26ea}'),
+    ('\u{26f2}', '\u{26f3}'),
+    ('\u{26f5}', '\u{26f5}'),
+    ('\u{26fa}', '\u{26fa}'),
+    ('\u{26fd}', '\u{26fd}'),
+    ('\u{2705}', '\u{2705}'),
+    ('\u{270a}', '\u{270b}'),
+    ('\u{2728}', '\u{2728}'),
+    ('\u{274c}', '\u{274c}'),
+    ('\u{274e}', '\u{274e}'),
+    ('\u{2753}', '\u{2755}'),
+    ('\u{2757}', '\u{2757}'),
+    ('\u{2795}', '\u{2797}'),
+    ('\u{27b0}', '\u{27b0}'),
+    ('\u{27bf}', '\u{27bf}'),
+    ('\u{2b1b}', '\u{2b1c}'),
+    ('\u{2b50}', '\u{2b50}'),
+    ('\u{2b55}', '\u{2b55}'),
+    ('\u{2e80}', '\u{303e}'),
+    ('\u{3041}', '\u{33ff}'),
+    ('\u{3400}', '\u{4dbf}'),
+    ('\u{4e00}', '\u{9fff}'),
+    ('\u{a000}', '\u{a4cf}'),
+    ('\u{a960}', '\u{a97f}'),
+    ('\u{ac00}', '\u{d7a3}'),
+    ('\u{f900}', '\u{faff}'),
+    ('\u{fe10}', '\u{fe19}'),
+    ('\u{fe30}', '\u{fe6f}'),
+    ('\u{ff00}', '\u{ff60}'),
+    ('\u{ffe0}', '\u{ffe6}'),
+    ('\u{16fe0}', '\u{16fe4}'),
+    ('\u{17000}', '\u{18cff}'),
+    ('\u{1b000}', '\u{1b2ff}'),
+    ('\u{1f004}', '\u{1f004}'),
+    ('\u{1f0cf}', '\u{1f0cf}'),
+    ('\u{1f18e}', '\u{1f18e}'),
+    ('\u{1f191}', '\u{1f19a}'),
+    ('\u{1f200}', '\u{1f202}'),
+    ('\u{1f210}', '\u{1f23b}'),
+    ('\u{1f240}', '\u{1f248}'),
+    ('\u{1f250}', '\u{1f251}'),
+    ('\u{1f260}', '\u{1f265}'),
+    ('\u{1f300}', '\u{1f320}'),
+    ('\u{1f32d}', '\u{1f335}'),
+    ('\u{1f337}', '\u{1f37c}'),
+    ('\u{1f37e}', '\u{1f393}'),
+    ('\u{1f3a0}', '\u{1f3ca}'),
+    ('\u{1f3cf}', '\u{1f3d3}'),
+    ('\u{1f3e0}', '\u{1f3f0}'),
+    ('\u{1f3f4}', '\u{1f3f4}'),
+    ('\u{1f3f8}', '\u{1f43e}'),
+    ('\u{1f440}', '\u{1f440}'),
+    ('\u{1f442}', '\u{1f4fc}'),
+    ('\u{1f4ff}', '\u{1f53d}'),
+    ('\u{1f54b}', '\u{1f54e}'),
+    ('\u{1f550}', '\u{1f567}'),
+    ('\u{1f57a}', '\u{1f57a}'),
+    ('\u{1f595}', '\u{1f596}'),
+    ('\u{1f5a4}', '\u{1f5a4}'),
+    ('\u{1f5fb}', '\u{1f64f}'),
+    ('\u{1f680}', '\u{1f6c5}'),
+    ('\u{1f6cc}', '\u{1f6cc}'),
+    ('\u{1f6d0}', '\u{1f6d2}'),
+    ('\u{1f6d5}', '\u{1f6d7}'),
+    ('\u{1f6dc}', '\u{1f6df}'),
+    ('\u{1f6eb}', '\u{1f6ec}'),
+    ('\u{1f6f4}', '\u{1f6fc}'),
+    ('\u{1f7e0}', '\u{1f7eb}'),
+    ('\u{1f7f0}', '\u{1f7f0}'),
+    ('\u{1f90c}', '\u{1f93a}'),
+    ('\u{1f93c}', '\u{1f945}'),
+    ('\u{1f947}', '\u{1f9ff}'),
+    ('\u{1fa70}', '\u{1faff}'),
+    ('\u{20000}', '\u{2fffd}'),
+    ('\u{30000}', '\u{3fffd}'),
+];
+
+fn in_table(table: &[(char, char)], c: char) -> bool {
+    table.binary_search_by(|&(lo, hi)| {
+        if hi < c {
+            std::cmp::Ordering::Less
+        } else if lo > c {
+            std::cmp::Ordering::Greater
+        } else {
+            std::cmp::Ordering::Equal
+        }
+    }).is_ok()
+}
+
+fn is_regional_indicator(c: char) -> bool {
+    ('\u{1f1e6}'..='\u{1f1ff}').contains(&c)
+}
+
+/// How many terminal cells `c` takes up when it starts a cluster.
+pub(crate) fn char_width(c: char) -> u32 {
+    if c.is_control() || in_table(EXTEND, c) || c == '\u{200b}' || c == '\u{ad}' {
+        0
+    } else if in_table(WIDE, c) {
+        2
+    } else {
+        1
+    }
+}
+
+/// Keeps track of where grapheme clusters start and end in a line of text.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct ClusterState {
+    prev: Option<char>, // of the cluster being built, if any
+    width: u32, // of the cluster being built, so far
+    odd_regional_indicators: bool, // whether the cluster so far is a lone half of a flag
+}
+
+impl ClusterState {
+    /// Add printable character `c` to the text, and return how many cells the screen position
+    /// moves, if `c` starts a new cluster, or `None` if it continues the
+    /// previous one.
+    ///
+    /// A cluster can still get wider after it's started (e.g. an emoji
+    /// presentation selector turning a 1-cell symbol into a 2-cell emoji);
+    /// that's included in the width returned for the next cluster.
+    pub(crate) fn add(&mut self, c: char) -> Option<u32> {
+        let continues = match self.prev {
+            None => false,
+            Some(ZWJ) => true,
+            Some(prev) if is_regional_indicator(prev) && is_regional_indicator(c) =>
+                self.odd_regional_indicators,
+            Some(_) => in_table(EXTEND, c),
+        };
+
+        if continues {
+            if c == VS16 && self.width == 1 {
+                self.width = 2;
+            }
+            if is_regional_indicator(c) {
+                self.odd_regional_indicators = false;
+                self.width = 2; // the pair is one flag
+            }
+            self.prev = Some(c);
+            None
+        } else {
+            let advance = self.width;
+            self.prev = Some(c);
+            self.width = char_width(c);
+            self.odd_regional_indicators = is_regional_indicator(c);
+            Some(advance)
+        }
+    }
+
+    /// The width of the cluster being built, once there's nothing left to add to it.
+    pub(crate) fn finish(&mut self) -> u32 {
+        let width = self.width;
+        *self = ClusterState::default();
+        width
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Split `text` into its clusters, each with its width.
+    fn clusters(text: &str) -> Vec<(String, u32)> {
+        let mut state = ClusterState::default();
+        let mut clusters = Vec::new();
+        let mut current = String::new();
+        for c in text.chars() {
+            if let Some(width) = state.add(c) {
+                if !current.is_empty() {
+                    clusters.push((std::mem::take(&mut current), width));
+                }
+            }
+            current.push(c);
+        }
+        if !current.is_empty() {
+            clusters.push((current, state.finish()));
+        }
+        clusters
+    }
+
+    fn cluster(text: &str, width: u32) -> (String, u32) {
+        (text.to_string(), width)
+    }
+
+    #[test]
+    fn tables_are_sorted() {
+        for table in [EXTEND, WIDE] {
+            assert!(table.iter().all(|(lo, hi)| lo <= hi));
+            assert!(table.windows(2).all(|pair| pair[0].1 < pair[1].0));
+        }
+    }
+
+    #[test]
+    fn ascii() {
+        assert_eq!(clusters("ab"), [cluster("a", 1), cluster("b", 1)]);
+    }
+
+    #[test]
+    fn combining_marks() {
+        // e + combining acute, then a + combining ring + combining dot below
+        assert_eq!(clusters("e\u{301}a\u{30a}\u{323}!"),
+            [cluster("e\u{301}", 1), cluster("a\u{30a}\u{323}", 1), cluster("!", 1)]);
+    }
+
+    #[test]
+    fn letters_among_marks() {
+        // each of these sits between combining marks of its script, but is a
+        // letter (or digit, or symbol) of its own
+        for letter in ['\u{081a}', '\u{09bd}', '\u{09ce}', '\u{0abd}', '\u{0b3d}', '\u{0bd0}',
+                       '\u{0cbd}', '\u{0d4e}', '\u{0d54}', '\u{0de6}', '\u{0f36}'] {
+            assert!(!in_table(EXTEND, letter), "U+{:04X}", letter as u32);
+            assert_eq!(clusters(&format!("a{letter}")).len(), 2, "U+{:04X}", letter as u32);
+        }
+        // while the marks around them still attach to what's before
+        assert_eq!(clusters("\u{0995}\u{09bc}\u{09cd}"), [cluster("\u{0995}\u{09bc}\u{09cd}", 1)]);
+        assert_eq!(clusters("\u{0b95}\u{0bcd}\u{0bd7}"), [cluster("\u{0b95}\u{0bcd}\u{0bd7}", 1)]);
+    }
+
+    #[test]
+    fn cjk() {
+        assert_eq!(clusters("\u{4e2d}\u{6587}a"), [cluster("\u{4e2d}", 2), cluster("\u{6587}", 2), cluster("a", 1)]);
+        // hangul and fullwidth forms too
+        assert_eq!(clusters("\u{d55c}\u{ff21}"), [cluster("\u{d55c}", 2), cluster("\u{ff21}", 2)]);
+    }
+
+    #[test]
+    fn zwj_emoji() {
+        // woman + ZWJ + laptop
+        let technologist = "\u{1f469}\u{200d}\u{1f4bb}";
+        // rainbow flag: white flag + VS16 + ZWJ + rainbow
+        let rainbow_flag = "\u{1f3f3}\u{fe0f}\u{200d}\u{1f308}";
+        assert_eq!(clusters(&format!("{technologist}{rainbow_flag}x")),
+            [cluster(technologist, 2), cluster(rainbow_flag, 2), cluster("x", 1)]);
+    }
+
+    #[test]
+    fn emoji_modifiers_and_selectors() {
+        // thumbs up + medium skin tone
+        assert_eq!(clusters("\u{1f44d}\u{1f3fd}"), [cluster("\u{1f44d}\u{1f3fd}", 2)]);
+        // a 1-cell symbol is widened by the emoji presentation selector
+        assert_eq!(clusters("\u{263a}x\u{263a}\u{fe0f}x"),
+            [cluster("\u{263a}", 1), cluster("x", 1), cluster("\u{263a}\u{fe0f}", 2), cluster("x", 1)]);
+    }
+
+    #[test]
+    fn regional_indicators_pair_up() {
+        let us = "\u{1f1fa}\u{1f1f8}";
+        let fr = "\u{1f1eb}\u{1f1f7}";
+        assert_eq!(clusters(&format!("{us}{fr}")), [cluster(us, 2), cluster(fr, 2)]);
+        // an odd one out is a cluster on its own
+        assert_eq!(clusters(&format!("{us}\u{1f1eb}")), [cluster(us, 2), cluster("\u{1f1eb}", 1)]);
+    }
+
+    #[test]
+    fn zero_width() {
+        assert_eq!(char_width('\u{200b}'), 0);
+        assert_eq!(char_width('\u{ad}'), 0);
+        assert_eq!(char_width('\t'), 0);
+    }
+
+    #[test]
+    fn finish_starts_over() {
+        let mut state = ClusterState::default();
+        assert_eq!(state.add('\u{4e2d}'), Some(0));
+        assert_eq!(state.finish(), 2);
+        // a combining mark after a fresh start has nothing to attach to
+        assert_eq!(state.add('\u{301}'), Some(0));
+        assert_eq!(state.finish(), 0);
+    }
+}
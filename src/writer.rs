@@ -0,0 +1,249 @@
+use std::io::{self, Write};
+
+use crate::{Colorizer, ColorizerState, COLOR_RESET};
+
+/// Wraps a [`Write`], colorizing everything written through it.
+///
+/// Line, column and escape-sequence state is carried across calls to
+/// `write`, so input can be split at arbitrary byte boundaries, including
+/// in the middle of a UTF-8 sequence. Bytes that aren't valid UTF-8 are
+/// passed through uncolored, or with
+/// [`with_replace_invalid`](QueercatWriter::with_replace_invalid), replaced
+/// with U+FFFD; either way, [`invalid_bytes`](QueercatWriter::invalid_bytes)
+/// counts them.
+///
+/// Call [`finish`](QueercatWriter::finish) when done to reset the terminal
+/// color and get the inner writer back.
+pub struct QueercatWriter<'a, W: Write> {
+    inner: W,
+    colorizer: Colorizer<'a>,
+    state: ColorizerState,
+    pending: Vec<u8>, // trailing bytes of an incomplete UTF-8 sequence
+    buf: String,
+    replace_invalid: bool,
+    invalid_bytes: u64,
+    threads: usize,
+}
+
+impl<'a, W: Write> QueercatWriter<'a, W> {
+    pub fn new(inner: W, colorizer: Colorizer<'a>) -> Self {
+        QueercatWriter {
+            inner,
+            colorizer,
+            state: ColorizerState::default(),
+            pending: Vec::new(),
+            buf: String::new(),
+            replace_invalid: false,
+            invalid_bytes: 0,
+            threads: 1,
+        }
+    }
+
+    /// Colorize big writes on up to this many threads; see
+    /// [`Colorizer::colorize_parallel_into`].
+    pub fn with_threads(self, threads: usize) -> Self {
+        QueercatWriter { threads, ..self }
+    }
+
+    /// Replace bytes that aren't valid UTF-8 with U+FFFD (one per invalid
+    /// sequence, like [`String::from_utf8_lossy`]) instead of passing them through.
+    pub fn with_replace_invalid(self, replace_invalid: bool) -> Self {
+        QueercatWriter { replace_invalid, ..self }
+    }
+
+    /// How many bytes written so far weren't valid UTF-8.
+    ///
+    /// An incomplete sequence at the end counts, since by the time this is
+    /// asked there's usually nothing more coming to complete it.
+    pub fn invalid_bytes(&self) -> u64 {
+        self.invalid_bytes + self.pending.len() as u64
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Flush anything held back, reset the color, and return the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.buf.clear();
+        self.colorizer.flush_held_into(&mut self.state, &mut self.buf);
+        self.inner.write_all(self.buf.as_bytes())?;
+
+        let pending = std::mem::take(&mut self.pending);
+        if !pending.is_empty() {
+            self.write_invalid(&pending)?;
+        }
+
+        if self.colorizer.color_output {
+            self.inner.write_all(COLOR_RESET.as_bytes())?;
+        }
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn write_valid(&mut self, text: &str) -> io::Result<()> {
+        self.buf.clear();
+        self.colorizer.colorize_parallel_into(&mut self.state, text, self.threads, &mut self.buf);
+        self.inner.write_all(self.buf.as_bytes())
+    }
+
+    fn write_invalid(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.invalid_bytes += bytes.len() as u64;
+        if self.replace_invalid {
+            self.write_valid("\u{fffd}")
+        } else {
+            self.inner.write_all(bytes)
+        }
+    }
+
+    fn write_bytes(&mut self, mut bytes: &[u8]) -> io::Result<()> {
+        loop {
+            match std::str::from_utf8(bytes) {
+                Ok(text) => return self.write_valid(text),
+                Err(e) => {
+                    let (valid, rest) = bytes.split_at(e.valid_up_to());
+                    self.write_valid(std::str::from_utf8(valid).expect("prefix already validated"))?;
+
+                    match e.error_len() {
+                        Some(len) => {
+                            self.write_invalid(&rest[..len])?;
+                            bytes = &rest[len..];
+                        }
+                        None => {
+                            // might be completed by the next write
+                            self.pending.extend_from_slice(rest);
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<W: Write> Write for QueercatWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.pending.is_empty() {
+            self.write_bytes(buf)?;
+        } else {
+            let mut joined = std::mem::take(&mut self.pending);
+            joined.extend_from_slice(buf);
+            self.write_bytes(&joined)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lookup_pattern, OutputColorType, StripMode};
+
+    fn colorizer() -> Colorizer<'static> {
+        let flag = lookup_pattern("rainbow").expect("built-in flag");
+        Colorizer::new(flag, 0.23, 0.1, 0.0, OutputColorType::TwentyFourBit)
+    }
+
+    /// What comes out of a writer that's given `input` `size` bytes at a time.
+    fn write_in_pieces(colorizer: Colorizer, input: &[u8], size: usize) -> Vec<u8> {
+        let mut writer = QueercatWriter::new(Vec::new(), colorizer);
+        for piece in input.chunks(size) {
+            writer.write_all(piece).expect("writing to a Vec");
+        }
+        writer.finish().expect("writing to a Vec")
+    }
+
+    #[test]
+    fn split_anywhere() {
+        let input = "h\u{e9}llo \u{1f3f3}\u{fe0f}\u{200d}\u{1f308} \u{4e2d}\u{6587}\n\x1b[1mbold\x1b[0m \x1b]8;;url\x1b\\link\x1b]8;;\x1b\\\n\tend";
+        let expected = colorizer().colorize(input);
+        for size in [1, 2, 3, 5, 7, input.len()] {
+            let output = write_in_pieces(colorizer(), input.as_bytes(), size);
+            assert_eq!(String::from_utf8(output).expect("valid UTF-8"), expected, "{size} bytes at a time");
+        }
+    }
+
+    #[test]
+    fn escape_split_across_writes() {
+        let mut writer = QueercatWriter::new(Vec::new(), colorizer());
+        writer.write_all(b"ab\x1b[3").expect("writing to a Vec");
+        writer.write_all(b"1mcd").expect("writing to a Vec");
+        let output = writer.finish().expect("writing to a Vec");
+        assert_eq!(String::from_utf8(output).expect("valid UTF-8"), colorizer().colorize("ab\x1b[31mcd"));
+    }
+
+    #[test]
+    fn finish_flushes_held_sequence() {
+        // the stripper holds "\x1b[3" back, waiting to see if it's SGR
+        let stripping = || colorizer().with_strip(StripMode::Sgr);
+        let input = "abc\x1b[3";
+        let output = write_in_pieces(stripping(), input.as_bytes(), 1);
+        let output = String::from_utf8(output).expect("valid UTF-8");
+        assert_eq!(output, stripping().colorize(input));
+        assert!(output.ends_with(&format!("\x1b[3{COLOR_RESET}")));
+    }
+
+    /// What comes out of a writer given `input` in one go, and how many
+    /// invalid bytes it counted before finishing.
+    fn write_invalid(input: &[u8], replace_invalid: bool) -> (Vec<u8>, u64) {
+        let mut writer = QueercatWriter::new(Vec::new(), colorizer())
+            .with_replace_invalid(replace_invalid);
+        writer.write_all(input).expect("writing to a Vec");
+        let invalid = writer.invalid_bytes();
+        (writer.finish().expect("writing to a Vec"), invalid)
+    }
+
+    #[test]
+    fn invalid_bytes_passed_through() {
+        let (output, invalid) = write_invalid(b"a\xffb", false);
+        let a = colorizer().colorize("a");
+        let a = a.strip_suffix(COLOR_RESET).expect("ends with a reset");
+        assert!(output.starts_with(&[a.as_bytes(), b"\xff"].concat()));
+        assert_eq!(invalid, 1);
+
+        // "b" carries on from where "a" left off, as if the byte wasn't there
+        let without_invalid: Vec<u8> = output.iter().copied().filter(|b| *b != 0xff).collect();
+        assert_eq!(without_invalid, colorizer().colorize("ab").into_bytes());
+    }
+
+    #[test]
+    fn invalid_bytes_replaced() {
+        let (output, invalid) = write_invalid(b"a\xffb", true);
+        assert_eq!(String::from_utf8(output).expect("valid UTF-8"), colorizer().colorize("a\u{fffd}b"));
+        assert_eq!(invalid, 1);
+    }
+
+    #[test]
+    fn truncated_sequence_at_the_end() {
+        // the first two bytes of a three-byte character; counted while still
+        // pending, since nothing's going to complete it
+        let (output, invalid) = write_invalid(b"a\xe4\xb8", false);
+        assert_eq!(invalid, 2);
+        let a = colorizer().colorize("a");
+        let a = a.strip_suffix(COLOR_RESET).expect("ends with a reset");
+        assert_eq!(output, [a.as_bytes(), b"\xe4\xb8", COLOR_RESET.as_bytes()].concat());
+
+        let (output, invalid) = write_invalid(b"a\xe4\xb8", true);
+        assert_eq!(invalid, 2);
+        assert_eq!(String::from_utf8(output).expect("valid UTF-8"), colorizer().colorize("a\u{fffd}"));
+    }
+
+    #[test]
+    fn truncated_sequence_completed_later() {
+        let mut writer = QueercatWriter::new(Vec::new(), colorizer());
+        writer.write_all(b"\xe4\xb8").expect("writing to a Vec");
+        assert_eq!(writer.invalid_bytes(), 2);
+        writer.write_all(b"\xad").expect("writing to a Vec");
+        assert_eq!(writer.invalid_bytes(), 0);
+        let output = writer.finish().expect("writing to a Vec");
+        assert_eq!(String::from_utf8(output).expect("valid UTF-8"), colorizer().colorize("\u{4e2d}"));
+    }
+}